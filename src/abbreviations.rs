@@ -0,0 +1,159 @@
+//! Per-country abbreviations of street and city names, e.g. `Str.` for `Straße`.
+//! Names are expanded when compressing and queries when serving,
+//! so abbreviated input matches the canonical names in the world object.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Used unless a table is given with `macs compress --abbreviations`.
+/// Abbreviations starting with `-` are word endings, e.g. `Hauptstr.`.
+const DEFAULTS: &[(&str, &str, &str)] = &[
+    ("AT", "-str.", "straße"),
+    ("AT", "Str.", "Straße"),
+    ("AT", "Pl.", "Platz"),
+    ("CH", "-str.", "strasse"),
+    ("CH", "Str.", "Strasse"),
+    ("DE", "-str.", "straße"),
+    ("DE", "Str.", "Straße"),
+    ("DE", "Pl.", "Platz"),
+    ("FR", "Av.", "Avenue"),
+    ("FR", "Bd", "Boulevard"),
+    ("FR", "Pl.", "Place"),
+    ("FR", "St", "Saint"),
+    ("FR", "Ste", "Sainte"),
+    ("GB", "Ave", "Avenue"),
+    ("GB", "Rd", "Road"),
+    ("GB", "Sq", "Square"),
+    ("US", "Ave", "Avenue"),
+    ("US", "Blvd", "Boulevard"),
+    ("US", "Rd", "Road"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Abbreviations {
+    /// Country code to (abbreviation, full form).
+    countries: BTreeMap<String, Vec<(String, String)>>,
+}
+
+impl Abbreviations {
+    pub fn defaults() -> Self {
+        let mut abbreviations = Self::default();
+        for (country, abbreviation, full) in DEFAULTS {
+            abbreviations.insert(country, abbreviation, full);
+        }
+        abbreviations
+    }
+
+    /// Read a json file like `{"DE": {"Str.": "Straße", "-str.": "straße"}}`.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read abbreviations {:?}: {}", path, e))?;
+        let table: BTreeMap<String, BTreeMap<String, String>> =
+            serde_json::from_str(&text).map_err(|e| format!("Invalid abbreviations: {}", e))?;
+        let mut abbreviations = Self::default();
+        for (country, entries) in table.iter() {
+            for (abbreviation, full) in entries.iter() {
+                abbreviations.insert(country, abbreviation, full);
+            }
+        }
+        Ok(abbreviations)
+    }
+
+    fn insert(&mut self, country: &str, abbreviation: &str, full: &str) {
+        self.countries
+            .entry(country.to_uppercase())
+            .or_default()
+            .push((abbreviation.to_string(), full.to_string()));
+    }
+
+    /// Add the countries of `other` this table does not know yet.
+    pub fn merge(&mut self, other: Abbreviations) {
+        for (country, entries) in other.countries {
+            self.countries.entry(country).or_insert(entries);
+        }
+    }
+
+    /// Replace abbreviated words by their full form.
+    /// With `prefix`, the last word might be incomplete and is only expanded if it ends with a dot.
+    pub fn expand(&self, country: &str, text: &str, prefix: bool) -> String {
+        let table = match self.countries.get(&country.to_uppercase()) {
+            Some(table) => table,
+            None => return text.to_string(),
+        };
+        let words: Vec<&str> = text.split(' ').collect();
+        words
+            .iter()
+            .enumerate()
+            .map(
+                |(i, word)| match !prefix || i + 1 < words.len() || word.ends_with('.') {
+                    true => expand_word(table, word),
+                    false => word.to_string(),
+                },
+            )
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// The distinct expansions of a name in every country with a table,
+    /// for names whose country is only known once autofixed.
+    pub fn expand_in_any(&self, text: &str) -> BTreeSet<String> {
+        self.countries
+            .keys()
+            .map(|country| self.expand(country, text, false))
+            .filter(|expanded| expanded != text)
+            .collect()
+    }
+}
+
+fn expand_word(table: &[(String, String)], word: &str) -> String {
+    let base = word.trim_end_matches('.');
+    for (abbreviation, full) in table {
+        match abbreviation.strip_prefix('-') {
+            // Word endings need the dot, "Hauptstr" might also be the start of "Hauptstraße".
+            Some(ending) => {
+                let ending = ending.trim_end_matches('.');
+                let stem_len = base.len().saturating_sub(ending.len());
+                if word.ends_with('.')
+                    && stem_len > 0
+                    && base.is_char_boundary(stem_len)
+                    && base[stem_len..].to_lowercase() == ending.to_lowercase()
+                {
+                    return format!("{}{}", &base[..stem_len], full.trim_start_matches('-'));
+                }
+            }
+            None => {
+                if abbreviation.trim_end_matches('.').to_lowercase() == base.to_lowercase() {
+                    return full.clone();
+                }
+            }
+        }
+    }
+    word.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expand_defaults() {
+        let a = Abbreviations::defaults();
+        assert_eq!(a.expand("DE", "Hauptstr. 5", false), "Hauptstraße 5");
+        assert_eq!(
+            a.expand("de", "Str. des 17. Juni", false),
+            "Straße des 17. Juni"
+        );
+        assert_eq!(
+            a.expand("FR", "Av. St Michel", false),
+            "Avenue Saint Michel"
+        );
+        assert_eq!(a.expand("FR", "Rue St", true), "Rue St");
+        assert_eq!(a.expand("DE", "Hauptstr", true), "Hauptstr");
+        assert_eq!(a.expand("DE", "Hauptstr", false), "Hauptstr");
+        assert_eq!(a.expand("NL", "Str.", false), "Str.");
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::OpenOptions,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use osmpbfreader::{OsmId, OsmObj};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    osm_xml::{open_maybe_compressed, read_osm_change, Action},
+    parse_coordinates::{is_address, obj_to_address, IncompleteAddressCoord},
+};
+
+/// Intermediate result of the parse stage, keyed by OSM id.
+/// Besides the addresses it keeps every object the addresses were computed from,
+/// so changes to e.g. a single node of a way can be applied later on.
+#[derive(Default, Serialize, Deserialize)]
+pub struct AddressStore {
+    objects: BTreeMap<OsmId, OsmObj>,
+    addresses: BTreeMap<OsmId, IncompleteAddressCoord>,
+}
+
+/// An address which has been added, modified or deleted by applying a change.
+/// For deletions, the address before the deletion is given.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AddressChange {
+    pub action: Action,
+    pub address: IncompleteAddressCoord,
+}
+
+fn direct_dependencies(obj: &OsmObj) -> Vec<OsmId> {
+    match obj {
+        OsmObj::Node(_) => Vec::new(),
+        OsmObj::Way(way) => way.nodes.iter().map(|n| OsmId::Node(*n)).collect(),
+        OsmObj::Relation(rel) => rel.refs.iter().map(|r| r.member).collect(),
+    }
+}
+
+impl AddressStore {
+    pub fn insert(
+        &mut self,
+        obj: OsmObj,
+        deps: BTreeMap<OsmId, OsmObj>,
+        address: IncompleteAddressCoord,
+    ) {
+        let id = obj.id();
+        self.objects.extend(deps);
+        self.objects.insert(id, obj);
+        self.addresses.insert(id, address);
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let reader = OpenOptions::new()
+            .read(true)
+            .create(false)
+            .open(path)
+            .map_err(|e| format!("Could not open address store {:?}: {}", path, e))?;
+        bincode::deserialize_from(BufReader::new(reader)).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let writer = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("Could not write address store {:?}: {}", path, e))?;
+        let mut writer = BufWriter::new(writer);
+        bincode::serialize_into(&mut writer, self).map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())
+    }
+
+    pub fn iter_addresses(&self) -> impl Iterator<Item = &IncompleteAddressCoord> {
+        self.addresses.values()
+    }
+
+    /// All objects an object depends on, recursively.
+    fn dependencies(&self, id: OsmId) -> HashSet<OsmId> {
+        let mut seen = HashSet::new();
+        let mut todo = vec![id];
+        while let Some(id) = todo.pop() {
+            if let Some(obj) = self.objects.get(&id) {
+                for dep in direct_dependencies(obj) {
+                    if seen.insert(dep) {
+                        todo.push(dep);
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// Map from every object to the addresses depending on it.
+    fn dependents(&self) -> HashMap<OsmId, Vec<OsmId>> {
+        let mut dependents: HashMap<OsmId, Vec<OsmId>> = HashMap::new();
+        for id in self.addresses.keys() {
+            for dep in self.dependencies(*id) {
+                dependents.entry(dep).or_default().push(*id);
+            }
+        }
+        dependents
+    }
+
+    /// Move dependencies of `id` which are not yet known from `pending` into the store.
+    fn adopt_dependencies(&mut self, id: OsmId, pending: &mut BTreeMap<OsmId, OsmObj>) {
+        let mut todo = vec![id];
+        while let Some(id) = todo.pop() {
+            let deps = match self.objects.get(&id) {
+                Some(obj) => direct_dependencies(obj),
+                None => continue,
+            };
+            for dep in deps {
+                if let Some(obj) = pending.remove(&dep) {
+                    self.objects.insert(dep, obj);
+                    todo.push(dep);
+                }
+            }
+        }
+    }
+
+    /// Apply the objects of an osmChange document.
+    /// Returns every address which has been created, modified or deleted.
+    pub fn apply(&mut self, changes: Vec<(Action, OsmObj)>) -> Vec<AddressChange> {
+        let dependents = self.dependents();
+        let mut affected: BTreeSet<OsmId> = BTreeSet::new();
+        // Changed objects the store does not know yet.
+        // They are only adopted if a new or changed address references them.
+        let mut pending: BTreeMap<OsmId, OsmObj> = BTreeMap::new();
+        for (action, obj) in changes {
+            let id = obj.id();
+            if let Some(addresses) = dependents.get(&id) {
+                affected.extend(addresses.iter().cloned());
+            }
+            match action {
+                Action::Delete => {
+                    self.objects.remove(&id);
+                    pending.remove(&id);
+                    if self.addresses.contains_key(&id) {
+                        affected.insert(id);
+                    }
+                }
+                Action::Create | Action::Modify => {
+                    if is_address(obj.tags()) || self.addresses.contains_key(&id) {
+                        affected.insert(id);
+                        self.objects.insert(id, obj);
+                    } else if self.objects.contains_key(&id) {
+                        self.objects.insert(id, obj);
+                    } else {
+                        pending.insert(id, obj);
+                    }
+                }
+            }
+        }
+
+        let mut result = Vec::new();
+        for id in affected {
+            self.adopt_dependencies(id, &mut pending);
+            let new = match self.objects.get(&id) {
+                Some(obj) if is_address(obj.tags()) => {
+                    match obj_to_address(obj.clone(), &self.objects) {
+                        Ok(address) => Some(address),
+                        Err(e) => {
+                            warn!("Could not update address of {:?}, keeping it: {}", id, e);
+                            continue;
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let old = self.addresses.remove(&id);
+            match (old, new) {
+                (None, Some(new)) => {
+                    self.addresses.insert(id, new.clone());
+                    result.push(AddressChange {
+                        action: Action::Create,
+                        address: new,
+                    });
+                }
+                (Some(old), Some(new)) => {
+                    self.addresses.insert(id, new.clone());
+                    if old != new {
+                        result.push(AddressChange {
+                            action: Action::Modify,
+                            address: new,
+                        });
+                    }
+                }
+                (Some(old), None) => result.push(AddressChange {
+                    action: Action::Delete,
+                    address: old,
+                }),
+                (None, None) => (),
+            }
+        }
+        result
+    }
+}
+
+/// Apply osmChange files to an address store created by `macs parse --store`.
+/// Writes the changed addresses as json lines to stdout, or, with `export`,
+/// all addresses of the updated store in the format of `macs parse`.
+pub fn update_store(store_path: PathBuf, change_files: Vec<PathBuf>, export: bool) -> Result<(), String> {
+    info!("Loading address store {:?}...", store_path);
+    let mut store = AddressStore::load(&store_path)?;
+    let mut stdout = io::stdout().lock();
+    for change_file in change_files {
+        info!("Applying {:?}...", change_file);
+        let changes = read_osm_change(open_maybe_compressed(&change_file)?)?;
+        let applied = store.apply(changes);
+        info!(
+            "{} addresses created, {} modified, {} deleted.",
+            applied.iter().filter(|c| c.action == Action::Create).count(),
+            applied.iter().filter(|c| c.action == Action::Modify).count(),
+            applied.iter().filter(|c| c.action == Action::Delete).count(),
+        );
+        if !export {
+            for change in applied {
+                serde_json::to_writer(&mut stdout, &change).map_err(|e| e.to_string())?;
+                stdout
+                    .write_all("\n".as_bytes())
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    if export {
+        for address in store.iter_addresses() {
+            serde_json::to_writer(&mut stdout, address).map_err(|e| e.to_string())?;
+            stdout
+                .write_all("\n".as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    info!("Writing address store {:?}...", store_path);
+    store.save(&store_path)
+}
+
+#[cfg(test)]
+mod test {
+    use osmpbfreader::{Node, NodeId, Tags, Way, WayId};
+
+    use super::*;
+
+    fn address_tags(street: &str, housenumber: &str) -> Tags {
+        let mut tags = Tags::new();
+        tags.insert("addr:city".into(), "Berlin".into());
+        tags.insert("addr:street".into(), street.into());
+        tags.insert("addr:housenumber".into(), housenumber.into());
+        tags
+    }
+
+    fn node(id: i64, tags: Tags, lon: i32, lat: i32) -> OsmObj {
+        OsmObj::Node(Node {
+            id: NodeId(id),
+            tags,
+            decimicro_lat: lat,
+            decimicro_lon: lon,
+        })
+    }
+
+    fn way(id: i64, tags: Tags, nodes: &[i64]) -> OsmObj {
+        OsmObj::Way(Way {
+            id: WayId(id),
+            tags,
+            nodes: nodes.iter().map(|n| NodeId(*n)).collect(),
+        })
+    }
+
+    fn insert(store: &mut AddressStore, obj: OsmObj, deps: Vec<OsmObj>) {
+        let deps: BTreeMap<OsmId, OsmObj> = deps.into_iter().map(|d| (d.id(), d)).collect();
+        let address = obj_to_address(obj.clone(), &deps).unwrap();
+        store.insert(obj, deps, address);
+    }
+
+    #[test]
+    fn apply_change_fixture() {
+        let mut store = AddressStore::default();
+        insert(
+            &mut store,
+            node(1, address_tags("Invalidenstraße", "1"), 133_800_000, 525_300_000),
+            vec![],
+        );
+        insert(
+            &mut store,
+            way(100, address_tags("Chausseestraße", "12"), &[2, 3]),
+            vec![
+                node(2, Tags::new(), 133_800_000, 525_300_000),
+                node(3, Tags::new(), 133_800_000, 525_300_000),
+            ],
+        );
+        insert(
+            &mut store,
+            way(101, address_tags("Torstraße", "5"), &[4, 5]),
+            vec![
+                node(4, Tags::new(), 134_000_000, 525_200_000),
+                node(5, Tags::new(), 134_000_000, 525_200_000),
+            ],
+        );
+
+        let changes =
+            read_osm_change(include_str!("../tests/fixtures/change.osc").as_bytes()).unwrap();
+        let applied = store.apply(changes);
+        let summary: Vec<(Action, OsmId, &str, i32)> = applied
+            .iter()
+            .map(|c| (c.action, c.address.id, c.address.housenumber.as_str(), c.address.lat))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Action::Modify, OsmId::Node(NodeId(1)), "1a", 525_300_000),
+                (Action::Create, OsmId::Node(NodeId(10)), "5", 525_200_000),
+                (Action::Modify, OsmId::Way(WayId(100)), "12", 525_350_000),
+                (Action::Delete, OsmId::Way(WayId(101)), "5", 525_200_000),
+            ]
+        );
+        assert_eq!(store.iter_addresses().count(), 3);
+    }
+}
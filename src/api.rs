@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{borrow::Cow, sync::Arc};
 
 use axum::{
     async_trait,
@@ -7,9 +7,13 @@ use axum::{
     routing::get,
     Json, Router, 
 };
-use serde::Deserialize;
+use osmpbfreader::OsmId;
+use serde::{Deserialize, Serialize};
 
-use crate::{compress::World, MAX_ITEMS_HEADER};
+use crate::{
+    compress::{PostalArea, Provenance, Street, World},
+    postcode, MAX_ITEMS_HEADER,
+};
 
 struct MaxItems(usize);
 
@@ -30,36 +34,169 @@ where
     }
 }
 
+/// Postal areas with the code in every district of the city, or only in the given one.
+fn get_postal_areas<'a>(
+    w: &'a World,
+    country_code: &str,
+    city_name: &str,
+    district: Option<&str>,
+    zip: &str,
+) -> Vec<&'a PostalArea> {
+    let city_name = w.expand_abbreviations(country_code, city_name, false);
+    let zip = postcode::normalize(&country_code.to_uppercase(), zip).unwrap_or(zip.to_string());
+    w.get_country(country_code.to_string())
+        .and_then(|country| country.get_city(city_name.as_str()))
+        .map(|city| city.get_postal_areas(&zip, district))
+        .unwrap_or_default()
+}
+
+/// The street in every matching postal area, as a street might span multiple districts.
+fn get_streets_named<'a>(
+    w: &'a World,
+    areas: &[&'a PostalArea],
+    country_code: &str,
+    street: &str,
+) -> Vec<&'a Street> {
+    let street = w.expand_abbreviations(country_code, street, false);
+    areas
+        .iter()
+        .filter_map(|area| area.get_street(street.as_str(), w))
+        .collect()
+}
+
 #[derive(Deserialize)]
 struct GetHousenumbersQuery {
     country_code: String,
     city_name: String,
+    district: Option<String>,
     zip: String,
     street: String,
     prefix: Option<String>,
+    osm_ids: Option<bool>,
+    /// `1` to return how each house number was completed.
+    verbose: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct HousenumberWithOsmId {
+    housenumber: String,
+    #[serde(with = "crate::osm_id::option")]
+    osm_id: Option<OsmId>,
 }
+
+#[derive(Serialize)]
+struct VerboseHousenumber {
+    housenumber: String,
+    #[serde(
+        with = "crate::osm_id::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    osm_id: Option<OsmId>,
+    /// `original`, or which fields the autofixer inferred.
+    provenance: Vec<&'static str>,
+    confidence: f64,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Housenumbers {
+    Plain(Vec<String>),
+    WithOsmIds(Vec<HousenumberWithOsmId>),
+    Verbose(Vec<VerboseHousenumber>),
+}
+
 async fn get_housenumbers(
     w: State<Arc<World>>,
     Query(q): Query<GetHousenumbersQuery>,
     MaxItems(m): MaxItems,
-) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+) -> Result<Json<Housenumbers>, (StatusCode, String)> {
     // let TypedHeader(max) = max_reasults.unwrap_or(TypedHeader(usize::MAX));
-    match w
-        .get_country(q.country_code)
-        .and_then(|country| country.get_city(q.city_name.as_str()))
-        .and_then(|city| city.get_postal_area(q.zip.as_str()))
-        .and_then(|postal_area| postal_area.get_street(q.street.as_str(), w.as_ref()))
-    {
-        None => Err((
+    let w = w.as_ref();
+    let areas = get_postal_areas(
+        w,
+        &q.country_code,
+        &q.city_name,
+        q.district.as_deref(),
+        &q.zip,
+    );
+    let streets = get_streets_named(w, &areas, &q.country_code, &q.street);
+    if streets.is_empty() {
+        return Err((
             StatusCode::NOT_FOUND,
             "Country/city/zip/street not found".to_string(),
-        )),
-        Some(street) => Ok(Json(
-            street
-                .iter_housenumbers_prefixed(q.prefix.unwrap_or("".into()), w.as_ref())
-                .take(m)
+        ));
+    }
+    let prefix = q.prefix.unwrap_or("".into());
+    let mut housenumbers: Vec<(String, Option<OsmId>, Provenance)> = streets
+        .iter()
+        .flat_map(|street| street.iter_housenumber_details_prefixed(prefix.clone(), w))
+        .collect();
+    if streets.len() > 1 {
+        housenumbers.sort_by(|a, b| a.0.cmp(&b.0));
+        housenumbers.dedup_by(|a, b| a.0 == b.0);
+    }
+    let housenumbers = housenumbers.into_iter().take(m);
+    let osm_ids = q.osm_ids.unwrap_or(false);
+    match (q.verbose.unwrap_or(0) > 0, osm_ids) {
+        (true, _) => Ok(Json(Housenumbers::Verbose(
+            housenumbers
+                .map(|(housenumber, osm_id, provenance)| VerboseHousenumber {
+                    housenumber,
+                    osm_id: osm_id.filter(|_| osm_ids),
+                    provenance: provenance.flags(),
+                    confidence: provenance.confidence(),
+                })
                 .collect(),
+        ))),
+        (false, true) => Ok(Json(Housenumbers::WithOsmIds(
+            housenumbers
+                .map(|(housenumber, osm_id, _)| HousenumberWithOsmId {
+                    housenumber,
+                    osm_id,
+                })
+                .collect(),
+        ))),
+        (false, false) => Ok(Json(Housenumbers::Plain(
+            housenumbers
+                .map(|(housenumber, _, _)| housenumber)
+                .collect(),
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+struct GetUnitsQuery {
+    country_code: String,
+    city_name: String,
+    district: Option<String>,
+    zip: String,
+    street: String,
+    housenumber: String,
+    prefix: Option<String>,
+}
+async fn get_units(
+    w: State<Arc<World>>,
+    Query(q): Query<GetUnitsQuery>,
+    MaxItems(m): MaxItems,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let w = w.as_ref();
+    let areas = get_postal_areas(
+        w,
+        &q.country_code,
+        &q.city_name,
+        q.district.as_deref(),
+        &q.zip,
+    );
+    let prefix = q.prefix.unwrap_or(String::new());
+    match get_streets_named(w, &areas, &q.country_code, &q.street)
+        .into_iter()
+        .find_map(|street| street.iter_units_prefixed(q.housenumber.as_str(), prefix.clone(), w))
+    {
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "Country/city/zip/street/housenumber not found".to_string(),
         )),
+        Some(units) => Ok(Json(units.take(m).cloned().collect())),
     }
 }
 
@@ -67,22 +204,63 @@ async fn get_housenumbers(
 struct GetStreetsQuery {
     country_code: String,
     city_name: String,
+    district: Option<String>,
     zip: String,
     prefix: Option<String>,
+    lang: Option<String>,
 }
 async fn get_streets(
     w: State<Arc<World>>,
     Query(q): Query<GetStreetsQuery>,
     MaxItems(m): MaxItems,
 ) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let w = w.as_ref();
+    let areas = get_postal_areas(
+        w,
+        &q.country_code,
+        &q.city_name,
+        q.district.as_deref(),
+        &q.zip,
+    );
+    if areas.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Country/city/zip not found".to_string(),
+        ));
+    }
+    let prefix = w.expand_abbreviations(&q.country_code, &q.prefix.unwrap_or_default(), true);
+    let mut streets: Vec<Cow<str>> = areas
+        .iter()
+        .flat_map(|area| area.iter_streets_prefixed(prefix.clone(), q.lang.as_deref(), w))
+        .collect();
+    if areas.len() > 1 {
+        streets.sort();
+        streets.dedup();
+    }
+    Ok(Json(
+        streets.into_iter().take(m).map(Cow::into_owned).collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct GetDistrictsQuery {
+    country_code: String,
+    city_name: String,
+    prefix: Option<String>,
+}
+async fn get_districts(
+    w: State<Arc<World>>,
+    Query(q): Query<GetDistrictsQuery>,
+    MaxItems(m): MaxItems,
+) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let city_name = w.expand_abbreviations(&q.country_code, &q.city_name, false);
     match w
         .get_country(q.country_code)
-        .and_then(|country| country.get_city(q.city_name.as_str()))
-        .and_then(|city| city.get_postal_area(q.zip.as_str()))
+        .and_then(|c| c.get_city(city_name.as_str()))
     {
-        None => Err((StatusCode::NOT_FOUND, "Country/city/zip not found".to_string())),
+        None => Err((StatusCode::NOT_FOUND, "Country/city not found".to_string())),
         Some(city) => Ok(Json(
-            city.iter_streets_prefixed(q.prefix.unwrap_or(String::new()), w.as_ref())
+            city.iter_districts_prefixed(q.prefix.unwrap_or(String::new()))
                 .take(m)
                 .cloned()
                 .collect(),
@@ -94,6 +272,7 @@ async fn get_streets(
 struct GetZipsQuery {
     country_code: String,
     city_name: String,
+    district: Option<String>,
     prefix: Option<String>,
 }
 async fn get_zips(
@@ -101,13 +280,14 @@ async fn get_zips(
     Query(q): Query<GetZipsQuery>,
     MaxItems(m): MaxItems,
 ) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let city_name = w.expand_abbreviations(&q.country_code, &q.city_name, false);
     match w
         .get_country(q.country_code)
-        .and_then(|c| c.get_city(q.city_name.as_str()))
+        .and_then(|c| c.get_city(city_name.as_str()))
     {
         None => Err((StatusCode::NOT_FOUND, "Country/city not found".to_string())),
         Some(city) => Ok(Json(
-            city.iter_zips_prefixed(q.prefix.unwrap_or(String::new()))
+            city.iter_zips_prefixed(q.prefix.unwrap_or(String::new()), q.district.as_deref())
                 .take(m)
                 .cloned()
                 .collect(),
@@ -119,12 +299,14 @@ async fn get_zips(
 struct GetCitiesQuery {
     country_code: String,
     prefix: Option<String>,
+    lang: Option<String>,
 }
 async fn get_cities(
     w: State<Arc<World>>,
     Query(q): Query<GetCitiesQuery>,
     MaxItems(m): MaxItems,
 ) -> Result<Json<Vec<String>>, (StatusCode, String)> {
+    let prefix = w.expand_abbreviations(&q.country_code, &q.prefix.unwrap_or_default(), true);
     match w.get_country(q.country_code.clone()) {
         None => Err((
             StatusCode::NOT_FOUND,
@@ -132,7 +314,7 @@ async fn get_cities(
         )),
         Some(country) => Ok(Json(
             country
-                .iter_cities_prefixed(q.prefix.unwrap_or(String::new()))
+                .iter_cities_prefixed(prefix, q.lang.as_deref())
                 .take(m)
                 .cloned()
                 .collect(),
@@ -143,8 +325,10 @@ async fn get_cities(
 pub fn get_app(world: World) -> Router {
     Router::new()
         .route("/cities", get(get_cities))
+        .route("/districts", get(get_districts))
         .route("/zips", get(get_zips))
         .route("/streets", get(get_streets))
         .route("/housenumbers", get(get_housenumbers))
+        .route("/units", get(get_units))
         .with_state(Arc::new(world))
 }
@@ -1,6 +1,15 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    compress::{City, Country, World},
+    compress::{City, Country, Provenance, World},
     parse::{Address, IncompleteAddress},
+    postcode,
 };
 
 pub fn is_unfixable(a: &IncompleteAddress) -> bool {
@@ -28,22 +37,169 @@ pub fn is_unfixable(a: &IncompleteAddress) -> bool {
     true
 }
 
-fn get_country_from_city_zip(w: &World, city: String, zip: Option<String>) -> Option<&Country> {
-    let mut potential_countries: Vec<(&Country, &City)> = Vec::new();
-    for potential_country in w.iter_countries() {
-        for potential_city in potential_country.iter_cities().filter(|c| c.name == city) {
-            potential_countries.push((potential_country, potential_city));
-        }
+/// Meters per decimicro degree of latitude.
+const METERS_PER_DECIMICRO: f64 = 0.011_132;
+/// Side of a grid cell in decimicro degrees, about 1km of latitude.
+const CELL_SIZE: i32 = 100_000;
+
+/// Distance in meters between two locations in decimicro degrees,
+/// good enough for neighbours within a few kilometers.
+fn distance((long_a, lat_a): (i32, i32), (long_b, lat_b): (i32, i32)) -> f64 {
+    let lat = (lat_a as f64 + lat_b as f64) / 2. / 10_000_000.;
+    let dx = (long_a as f64 - long_b as f64) * lat.to_radians().cos();
+    let dy = lat_a as f64 - lat_b as f64;
+    (dx * dx + dy * dy).sqrt() * METERS_PER_DECIMICRO
+}
+
+fn cell((long, lat): (i32, i32)) -> (i32, i32) {
+    (long.div_euclid(CELL_SIZE), lat.div_euclid(CELL_SIZE))
+}
+
+/// Location of a complete address, as collected for [Locations].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Located {
+    pub street: String,
+    /// (country, city, zip)
+    pub area: (String, String, String),
+    pub long: i32,
+    pub lat: i32,
+}
+
+impl Located {
+    pub fn of(a: &Address) -> Option<Self> {
+        Some(Self {
+            street: a.street.clone(),
+            area: (a.country.clone(), a.city.clone(), a.postcode.clone()),
+            long: a.long?,
+            lat: a.lat?,
+        })
     }
-    if let Some(zip) = &zip {
-        potential_countries = potential_countries
+}
+
+/// Locations of complete addresses in a grid per street name,
+/// to infer fields of incomplete addresses from their nearest neighbours.
+#[derive(Default)]
+pub struct Locations {
+    /// Distinct (country, city, zip) of the located addresses.
+    areas: Vec<(String, String, String)>,
+    area_ids: HashMap<(String, String, String), u32>,
+    street_ids: HashMap<String, u32>,
+    /// (street, grid cell) to (area, longitude, latitude).
+    cells: HashMap<(u32, (i32, i32)), Vec<(u32, i32, i32)>>,
+}
+
+impl Locations {
+    pub fn insert(&mut self, located: Located) {
+        let Located {
+            street,
+            area,
+            long,
+            lat,
+        } = located;
+        let next_id = self.areas.len() as u32;
+        let area_id = *self.area_ids.entry(area.clone()).or_insert_with(|| {
+            self.areas.push(area);
+            next_id
+        });
+        let next_id = self.street_ids.len() as u32;
+        let street_id = *self.street_ids.entry(street).or_insert(next_id);
+        self.cells
+            .entry((street_id, cell((long, lat))))
+            .or_default()
+            .push((area_id, long, lat));
+    }
+
+    /// (country, city, zip) of the complete addresses on the street within `max_distance` meters,
+    /// nearest first.
+    fn nearest(
+        &self,
+        street: &str,
+        location: (i32, i32),
+        max_distance: f64,
+    ) -> Vec<&(String, String, String)> {
+        let street_id = match self.street_ids.get(street) {
+            Some(id) => *id,
+            None => return Vec::new(),
+        };
+        let lat = location.1 as f64 / 10_000_000.;
+        let cell_meters = CELL_SIZE as f64 * METERS_PER_DECIMICRO;
+        let rings_lat = (max_distance / cell_meters).ceil() as i32;
+        let rings_long =
+            (max_distance / (cell_meters * lat.to_radians().cos().max(0.01))).ceil() as i32;
+        let (x, y) = cell(location);
+        let mut located: Vec<(f64, u32)> = Vec::new();
+        for dx in -rings_long..=rings_long {
+            for dy in -rings_lat..=rings_lat {
+                let entries = self.cells.get(&(street_id, (x + dx, y + dy)));
+                for (area, long, lat) in entries.into_iter().flatten() {
+                    let d = distance((*long, *lat), location);
+                    if d <= max_distance {
+                        located.push((d, *area));
+                    }
+                }
+            }
+        }
+        located.sort_by(|a, b| a.0.total_cmp(&b.0));
+        located
             .into_iter()
-            .filter(|(_country, city)| city.get_postal_area(zip.as_str()).is_some())
-            .collect();
+            .map(|(_, area)| &self.areas[area as usize])
+            .collect()
+    }
+}
+
+/// Inverted indexes over the cities of the world, built once before autofixing,
+/// so fixing an address does not scan every city of every country.
+pub struct AutofixIndex<'a> {
+    world: &'a World,
+    /// City name to the cities of that name.
+    cities: HashMap<&'a str, Vec<(&'a Country, &'a City)>>,
+    /// Lowercase postal code to the cities having it.
+    zips: HashMap<String, Vec<(&'a Country, &'a City)>>,
+}
+
+impl<'a> AutofixIndex<'a> {
+    pub fn new(w: &'a World) -> Self {
+        let mut cities: HashMap<&str, Vec<(&Country, &City)>> = HashMap::new();
+        let mut zips: HashMap<String, Vec<(&Country, &City)>> = HashMap::new();
+        for country in w.iter_countries() {
+            for city in country.iter_cities() {
+                // Names in other languages might equal the name.
+                let names: BTreeSet<&str> = city.iter_names().map(|n| n.as_str()).collect();
+                for name in names {
+                    cities.entry(name).or_default().push((country, city));
+                }
+                for zip in city.iter_zips_prefixed(String::new(), None) {
+                    zips.entry(zip.to_lowercase())
+                        .or_default()
+                        .push((country, city));
+                }
+            }
+        }
+        Self {
+            world: w,
+            cities,
+            zips,
+        }
+    }
+}
+
+fn get_country_from_city_zip<'a>(
+    index: &AutofixIndex<'a>,
+    city: &str,
+    zip: Option<&str>,
+) -> Result<&'a Country, Unfixable> {
+    let mut potential_countries: Vec<(&Country, &City)> =
+        index.cities.get(city).cloned().unwrap_or_default();
+    if let Some(zip) = zip {
+        // The postal code could not be normalized without knowing the country.
+        potential_countries.retain(|(country, city)| {
+            let zip = postcode::normalize(&country.code, zip).unwrap_or(zip.to_string());
+            !city.get_postal_areas(&zip, None).is_empty()
+        });
     }
     match potential_countries.len() {
-        0 => None,
-        1 => Some(potential_countries[0].0),
+        0 => Err(Unfixable::UnknownCity),
+        1 => Ok(potential_countries[0].0),
         n => {
             log::debug!(
                 "There were {} matching countries for city/zip {:?}: {:?}",
@@ -54,28 +210,27 @@ fn get_country_from_city_zip(w: &World, city: String, zip: Option<String>) -> Op
                     .map(|(country, city)| format!("[{} - {}]", country.code, city.name))
                     .collect::<Vec<String>>()
             );
-            None
+            Err(Unfixable::AmbiguousCity { countries: n })
         }
     }
 }
 
-fn get_city_from_country_zip(w: &World, country: Option<String>, zip: String) -> Option<&City> {
-    let mut potential_cities: Vec<(&Country, &City)> = Vec::new();
-    for portential_country in w.iter_countries() {
-        if let Some(c_code) = &country {
-            if c_code != &portential_country.code {
-                continue;
-            }
-        }
-        for potential_city in portential_country.iter_cities() {
-            if let Some(_zip) = potential_city.get_postal_area(zip.as_str()) {
-                potential_cities.push((portential_country, potential_city))
-            }
-        }
+fn get_city_from_country_zip<'a>(
+    index: &AutofixIndex<'a>,
+    country: Option<&str>,
+    zip: &str,
+) -> Result<&'a City, Unfixable> {
+    let mut potential_cities: Vec<(&Country, &City)> = index
+        .zips
+        .get(&zip.to_lowercase())
+        .cloned()
+        .unwrap_or_default();
+    if let Some(c_code) = country {
+        potential_cities.retain(|(potential_country, _city)| potential_country.code == c_code);
     }
     match potential_cities.len() {
-        0 => None,
-        1 => Some(potential_cities[0].1),
+        0 => Err(Unfixable::UnknownZip),
+        1 => Ok(potential_cities[0].1),
         n => {
             log::debug!(
                 "There were {} matching cities for country/zip {:?}: {:?}",
@@ -86,71 +241,528 @@ fn get_city_from_country_zip(w: &World, country: Option<String>, zip: String) ->
                     .map(|(country, city)| format!("[{} - {}]", country.code, city.name))
                     .collect::<Vec<String>>()
             );
-            None
+            Err(Unfixable::AmbiguousZip { cities: n })
+        }
+    }
+}
+
+/// The postal area of the street, or among several, the one of the nearest complete address.
+fn get_zip_from_country_city_street(
+    index: &AutofixIndex,
+    locations: &Locations,
+    country: &str,
+    city: &str,
+    street: &str,
+    location: Option<(i32, i32)>,
+    max_distance: f64,
+) -> Result<(String, Method), Unfixable> {
+    let w = index.world;
+    let city = w
+        .get_country(country.to_string())
+        .ok_or(Unfixable::UnknownCountry)?
+        .get_city(city)
+        .ok_or(Unfixable::UnknownCity)?;
+    // Streets are sorted by their index into the pool, which is looked up once.
+    let index = w
+        .street_index(country, street)
+        .ok_or(Unfixable::UnknownStreet)? as u32;
+    let zips: BTreeSet<&String> = city
+        .iter_zips()
+        .filter(|area| area.get_street_by_index(index).is_some())
+        .map(|area| &area.code)
+        .collect();
+    match (zips.len(), location) {
+        (0, _) => Err(Unfixable::UnknownStreet),
+        (1, _) => Ok((zips.into_iter().next().unwrap().clone(), Method::Street)),
+        (n, None) => {
+            log::debug!(
+                "There were {} matching zips for country/city/street {:?} without location",
+                n,
+                (country, &city.name, street)
+            );
+            Err(Unfixable::AmbiguousStreet { zips: n })
         }
+        (_, Some(location)) => locations
+            .nearest(street, location, max_distance)
+            .into_iter()
+            .find(|(co, ci, zip)| co == country && ci == &city.name && zips.contains(zip))
+            .map(|(_, _, zip)| (zip.clone(), Method::NearestOnStreet))
+            .ok_or(Unfixable::NoNeighbours),
     }
 }
 
-// fn get_zip_from_country_city_street(w: &World, country:)
+/// Thresholds for inferring fields from the locations of complete addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct AutofixOptions {
+    /// Number of nearest complete addresses on the same street to consult.
+    pub neighbours: usize,
+    /// Share of `neighbours` which need to agree, fewer neighbours found count as disagreeing.
+    pub min_confidence: f64,
+    /// Neighbours further away, in meters, are not considered.
+    pub max_distance: f64,
+}
 
-pub fn try_autofixing(
-    w: &World,
-    mut incomplete_addresses: Vec<IncompleteAddress>,
-) -> (Vec<Address>, Vec<IncompleteAddress>) {
-    let mut unfixable: Vec<IncompleteAddress> = Vec::new();
-    let mut fixed: Vec<Address> = Vec::new();
-    while let Some(IncompleteAddress {
-        country,
-        city,
-        postcode,
-        street,
-        housenumber,
-    }) = incomplete_addresses.pop()
-    {
-        match (country, city, postcode, street, housenumber) {
-            (Some(co), Some(ci), Some(po), Some(st), Some(hn)) => fixed.push(Address {
-                country: co,
-                city: ci,
-                postcode: po,
-                street: st,
-                housenumber: hn,
-            }),
-            (None, Some(ci), po, st, hn) => {
-                match get_country_from_city_zip(w, ci.clone(), po.clone()) {
-                    None => unfixable.push(IncompleteAddress {
-                        country: None,
-                        city: Some(ci),
-                        postcode: po,
-                        street: st,
-                        housenumber: hn,
-                    }),
-                    Some(country) => incomplete_addresses.push(IncompleteAddress {
-                        country: Some(country.code.clone()),
-                        city: Some(ci),
-                        postcode: po,
-                        street: st,
-                        housenumber: hn,
-                    }),
+impl Default for AutofixOptions {
+    fn default() -> Self {
+        Self {
+            neighbours: 5,
+            min_confidence: 0.8,
+            max_distance: 500.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Field {
+    Country,
+    City,
+    Zip,
+}
+
+/// How a missing field was inferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Method {
+    /// Country of the only city with that name (and zip).
+    CityName,
+    /// City of the only postal area with that zip.
+    Zip,
+    /// Only postal area of the city containing the street.
+    Street,
+    /// Postal area of the nearest complete address on the street.
+    NearestOnStreet,
+    /// Agreeing nearest complete addresses on the street.
+    Neighbours,
+}
+
+/// Why an address could not be completed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Unfixable {
+    MissingStreet,
+    MissingHousenumber,
+    /// Neither enough fields to infer the others by name, nor a location.
+    TooFewFields,
+    MissingLocation,
+    UnknownCountry,
+    UnknownCity,
+    AmbiguousCity {
+        countries: usize,
+    },
+    UnknownZip,
+    AmbiguousZip {
+        cities: usize,
+    },
+    /// No postal area of the city contains the street.
+    UnknownStreet,
+    /// The street is in several postal areas, and the address has no location.
+    AmbiguousStreet {
+        zips: usize,
+    },
+    /// No complete address on the street nearby.
+    NoNeighbours,
+    NeighboursDisagree {
+        confidence: f64,
+    },
+}
+
+impl Unfixable {
+    /// The reason without details, e.g. `ambiguous_city`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Unfixable::MissingStreet => "missing_street",
+            Unfixable::MissingHousenumber => "missing_housenumber",
+            Unfixable::TooFewFields => "too_few_fields",
+            Unfixable::MissingLocation => "missing_location",
+            Unfixable::UnknownCountry => "unknown_country",
+            Unfixable::UnknownCity => "unknown_city",
+            Unfixable::AmbiguousCity { .. } => "ambiguous_city",
+            Unfixable::UnknownZip => "unknown_zip",
+            Unfixable::AmbiguousZip { .. } => "ambiguous_zip",
+            Unfixable::UnknownStreet => "unknown_street",
+            Unfixable::AmbiguousStreet { .. } => "ambiguous_street",
+            Unfixable::NoNeighbours => "no_neighbours",
+            Unfixable::NeighboursDisagree { .. } => "neighbours_disagree",
+        }
+    }
+}
+
+/// An address completed by the autofixer.
+#[derive(Debug)]
+pub struct Fixed {
+    pub address: Address,
+    pub inferred: Vec<(Field, Method)>,
+    /// Share of agreeing neighbours, 1 if all fields were inferred by name.
+    pub confidence: f64,
+}
+
+impl Fixed {
+    pub fn provenance(&self) -> Provenance {
+        let inferred = self
+            .inferred
+            .iter()
+            .map(|(field, method)| {
+                let field = match field {
+                    Field::Country => Provenance::INFERRED_COUNTRY,
+                    Field::City => Provenance::INFERRED_CITY,
+                    Field::Zip => Provenance::INFERRED_ZIP,
+                };
+                match method {
+                    Method::NearestOnStreet | Method::Neighbours => {
+                        field | Provenance::INTERPOLATED
+                    }
+                    _ => field,
+                }
+            })
+            .fold(0, |a, b| a | b);
+        Provenance::inferred(inferred, self.confidence)
+    }
+}
+
+fn field_mut(a: &mut IncompleteAddress, field: Field) -> &mut Option<String> {
+    match field {
+        Field::Country => &mut a.country,
+        Field::City => &mut a.city,
+        Field::Zip => &mut a.postcode,
+    }
+}
+
+/// Missing fields agreed on by the nearest complete addresses on the street,
+/// which match the known fields. Returns the share of agreeing neighbours.
+fn get_from_neighbours(
+    locations: &Locations,
+    options: &AutofixOptions,
+    a: &IncompleteAddress,
+) -> Result<(Vec<(Field, String)>, f64), Unfixable> {
+    let street = a.street.as_ref().ok_or(Unfixable::MissingStreet)?;
+    let location = a.long.zip(a.lat).ok_or(Unfixable::MissingLocation)?;
+    let known = |value: &Option<String>, candidate: &String| {
+        value.as_ref().map_or(true, |v| v == candidate)
+    };
+    let neighbours: Vec<&(String, String, String)> = locations
+        .nearest(street, location, options.max_distance)
+        .into_iter()
+        .filter(|(co, ci, zip)| {
+            known(&a.country, co) && known(&a.city, ci) && known(&a.postcode, zip)
+        })
+        .take(options.neighbours)
+        .collect();
+    let mut votes: BTreeMap<&(String, String, String), usize> = BTreeMap::new();
+    for neighbour in neighbours.iter() {
+        *votes.entry(neighbour).or_default() += 1;
+    }
+    // On a tie, the nearest neighbour wins.
+    let nearest = |area: &&(String, String, String)| neighbours.iter().position(|n| n == area);
+    let ((country, city, zip), count) = votes
+        .into_iter()
+        .max_by_key(|(area, count)| (*count, Reverse(nearest(area))))
+        .ok_or(Unfixable::NoNeighbours)?;
+    // Missing neighbours count as disagreeing, so a single one is not enough.
+    let confidence = count as f64 / options.neighbours.max(1) as f64;
+    if confidence < options.min_confidence {
+        log::debug!(
+            "Neighbours of {:?} only agree by {:.2}",
+            (&a.street, &a.housenumber),
+            confidence
+        );
+        return Err(Unfixable::NeighboursDisagree { confidence });
+    }
+    let fields: Vec<(Field, String)> = [
+        (Field::Country, &a.country, country),
+        (Field::City, &a.city, city),
+        (Field::Zip, &a.postcode, zip),
+    ]
+    .into_iter()
+    .filter(|(_, known, _)| known.is_none())
+    .map(|(field, _, value)| (field, value.clone()))
+    .collect();
+    Ok((fields, confidence))
+}
+
+/// Fill in missing fields one at a time, until the address is complete or no field can be inferred.
+/// By name first, by the locations of the neighbours otherwise.
+fn autofix(
+    index: &AutofixIndex,
+    locations: &Locations,
+    options: &AutofixOptions,
+    mut a: IncompleteAddress,
+) -> Result<Fixed, (IncompleteAddress, Unfixable)> {
+    if a.street.is_none() {
+        return Err((a, Unfixable::MissingStreet));
+    }
+    if a.housenumber.is_none() {
+        return Err((a, Unfixable::MissingHousenumber));
+    }
+    let mut inferred: Vec<(Field, Method)> = Vec::new();
+    let mut confidence = 1.;
+    loop {
+        let by_name = match (&a.country, &a.city, &a.postcode, &a.street, &a.housenumber) {
+            (Some(co), Some(ci), Some(_), Some(_), Some(_)) => {
+                // A city known by its name in another language is inserted by its name.
+                let city = index
+                    .world
+                    .get_country(co.clone())
+                    .and_then(|c| c.get_city(ci));
+                if let Some(city) = city {
+                    a.city = Some(city.name.clone());
                 }
+                return Ok(Fixed {
+                    address: a.into_complete().expect("address is complete"),
+                    inferred,
+                    confidence,
+                })
+            }
+            (None, Some(ci), po, _, _) => get_country_from_city_zip(index, ci, po.as_deref())
+                .map(|country| (Field::Country, country.code.clone(), Method::CityName)),
+            (co, None, Some(zip), _, _) => get_city_from_country_zip(index, co.as_deref(), zip)
+                .map(|city| (Field::City, city.name.clone(), Method::Zip)),
+            (Some(co), Some(ci), None, Some(st), Some(_)) => {
+                let location = a.long.zip(a.lat);
+                let max_distance = options.max_distance;
+                get_zip_from_country_city_street(
+                    index,
+                    locations,
+                    co,
+                    ci,
+                    st,
+                    location,
+                    max_distance,
+                )
+                .map(|(zip, method)| (Field::Zip, zip, method))
             }
-            (co, None, Some(zip), st, hn) => match get_city_from_country_zip(w, co.clone(), zip.clone()) {
-                None => unfixable.push(IncompleteAddress {
-                    country: co,
-                    city: None,
-                    postcode: Some(zip),
-                    street: st,
-                    housenumber: hn,
-                }),
-                Some(city) => incomplete_addresses.push(IncompleteAddress {
-                    country: co,
-                    city: Some(city.name.clone()),
-                    postcode: Some(zip),
-                    street: st,
-                    housenumber: hn,
-                }),
+            _ => Err(Unfixable::TooFewFields),
+        };
+        match by_name {
+            Ok((field, value, method)) => {
+                *field_mut(&mut a, field) = Some(value);
+                inferred.push((field, method));
+            }
+            Err(reason) => match get_from_neighbours(locations, options, &a) {
+                // Report why the fields could not be inferred by name, if there was a way to.
+                Err(neighbours_reason) => match reason {
+                    Unfixable::TooFewFields => return Err((a, neighbours_reason)),
+                    reason => return Err((a, reason)),
+                },
+                Ok((fields, share)) => {
+                    for (field, value) in fields {
+                        *field_mut(&mut a, field) = Some(value);
+                        inferred.push((field, Method::Neighbours));
+                    }
+                    confidence = f64::min(confidence, share);
+                }
             },
-            _ => (),
+        }
+    }
+}
+
+/// Addresses are fixed in parallel, the world is only read.
+/// `locations` needs to contain the complete addresses on the streets of the incomplete ones.
+pub fn try_autofixing(
+    index: &AutofixIndex,
+    locations: &Locations,
+    options: &AutofixOptions,
+    incomplete_addresses: Vec<IncompleteAddress>,
+) -> (Vec<Fixed>, Vec<(IncompleteAddress, Unfixable)>) {
+    let results: Vec<Result<Fixed, (IncompleteAddress, Unfixable)>> = incomplete_addresses
+        .into_par_iter()
+        .map(|a| autofix(index, locations, options, a))
+        .collect();
+    let mut unfixable: Vec<(IncompleteAddress, Unfixable)> = Vec::new();
+    let mut fixed: Vec<Fixed> = Vec::new();
+    for result in results {
+        match result {
+            Ok(a) => fixed.push(a),
+            Err(a) => unfixable.push(a),
         }
     }
     (fixed, unfixable)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compress::test::world_of;
+
+    fn incomplete(
+        country: Option<&str>,
+        city: Option<&str>,
+        zip: Option<&str>,
+    ) -> IncompleteAddress {
+        IncompleteAddress {
+            country: country.map(String::from),
+            city: city.map(String::from),
+            postcode: zip.map(String::from),
+            street: Some("Hauptstraße".into()),
+            housenumber: Some("1".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fix_with_index() {
+        let world = world_of(&[
+            ("DE", "Neustadt", "67433", "Hauptstraße", "2"),
+            ("DE", "Berlin", "10115", "Hauptstraße", "2"),
+            ("AT", "Neustadt", "4911", "Hauptstraße", "2"),
+        ]);
+        let (fixed, unfixable) = try_autofixing(
+            &AutofixIndex::new(&world),
+            &Locations::default(),
+            &AutofixOptions::default(),
+            vec![
+                incomplete(None, Some("Neustadt"), Some("4911")),
+                incomplete(None, None, Some("10115")),
+                incomplete(None, Some("Neustadt"), None),
+                incomplete(Some("DE"), None, Some("99999")),
+            ],
+        );
+        let fixed: Vec<(&str, &str)> = fixed
+            .iter()
+            .map(|f| (f.address.country.as_str(), f.address.city.as_str()))
+            .collect();
+        assert_eq!(fixed, vec![("AT", "Neustadt"), ("DE", "Berlin")]);
+        let reasons: Vec<&Unfixable> = unfixable.iter().map(|(_, reason)| reason).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                &Unfixable::AmbiguousCity { countries: 2 },
+                &Unfixable::UnknownZip
+            ]
+        );
+    }
+
+    #[test]
+    fn country_from_city_in_other_language() {
+        let mut world = world_of(&[("BE", "Brussel", "1000", "Hauptstraße", "2")]);
+        world.insert_city_name("BE", "Brussel", "fr".into(), "Bruxelles".into());
+        world.insert_city_name("BE", "Brussel", "nl".into(), "Brussel".into());
+        let (fixed, unfixable) = try_autofixing(
+            &AutofixIndex::new(&world),
+            &Locations::default(),
+            &AutofixOptions::default(),
+            vec![
+                incomplete(None, Some("Bruxelles"), Some("1000")),
+                incomplete(None, Some("Brussel"), None),
+            ],
+        );
+        let fixed: Vec<(&str, &str)> = fixed
+            .iter()
+            .map(|f| (f.address.country.as_str(), f.address.city.as_str()))
+            .collect();
+        assert_eq!(fixed, vec![("BE", "Brussel"), ("BE", "Brussel")]);
+        assert!(unfixable.is_empty());
+    }
+
+    #[test]
+    fn zip_from_nearest_neighbour() {
+        let world = world_of(&[
+            ("DE", "Berlin", "10115", "Hauptstraße", "2"),
+            ("DE", "Berlin", "10117", "Hauptstraße", "40"),
+            ("DE", "Potsdam", "14467", "Lindenstraße", "1"),
+        ]);
+        let mut locations = Locations::default();
+        for (zip, long, lat) in [
+            ("10115", 134_000_000, 525_300_000),
+            ("10117", 134_000_000, 525_100_000),
+        ] {
+            locations.insert(
+                Located::of(&Address {
+                    country: "DE".into(),
+                    city: "Berlin".into(),
+                    postcode: zip.into(),
+                    street: "Hauptstraße".into(),
+                    housenumber: "1".into(),
+                    long: Some(long),
+                    lat: Some(lat),
+                    ..Default::default()
+                })
+                .unwrap(),
+            );
+        }
+        let located = |lat: i32| IncompleteAddress {
+            long: Some(134_000_000),
+            lat: Some(lat),
+            ..incomplete(Some("DE"), Some("Berlin"), None)
+        };
+        let (fixed, unfixable) = try_autofixing(
+            &AutofixIndex::new(&world),
+            &locations,
+            &AutofixOptions::default(),
+            vec![
+                located(525_120_000),
+                located(525_280_000),
+                incomplete(Some("DE"), Some("Berlin"), None),
+                IncompleteAddress {
+                    street: Some("Lindenstraße".into()),
+                    ..incomplete(Some("DE"), Some("Potsdam"), None)
+                },
+            ],
+        );
+        let zips: Vec<&str> = fixed.iter().map(|f| f.address.postcode.as_str()).collect();
+        assert_eq!(zips, vec!["10117", "10115", "14467"]);
+        assert_eq!(unfixable.len(), 1);
+    }
+
+    #[test]
+    fn fields_from_neighbours() {
+        let world = world_of(&[("DE", "Berlin", "10115", "Hauptstraße", "2")]);
+        let mut locations = Locations::default();
+        // Four neighbours agree near the first location, only three near the second.
+        for (zip, lat) in [
+            ("10115", 525_300_000),
+            ("10115", 525_301_000),
+            ("10115", 525_302_000),
+            ("10115", 525_303_000),
+            ("10117", 525_304_000),
+            ("10115", 526_000_000),
+            ("10115", 526_001_000),
+            ("10115", 526_002_000),
+            ("10117", 526_003_000),
+            ("10117", 526_004_000),
+            // A single neighbour is not enough to agree.
+            ("10115", 528_000_000),
+        ] {
+            locations.insert(
+                Located::of(&Address {
+                    country: "DE".into(),
+                    city: "Berlin".into(),
+                    postcode: zip.into(),
+                    street: "Hauptstraße".into(),
+                    housenumber: "1".into(),
+                    long: Some(134_000_000),
+                    lat: Some(lat),
+                    ..Default::default()
+                })
+                .unwrap(),
+            );
+        }
+        let located = |lat: i32| IncompleteAddress {
+            long: Some(134_000_000),
+            lat: Some(lat),
+            ..incomplete(None, None, None)
+        };
+        let (fixed, unfixable) = try_autofixing(
+            &AutofixIndex::new(&world),
+            &locations,
+            &AutofixOptions::default(),
+            vec![
+                located(525_300_500),
+                located(526_000_500),
+                located(530_000_000),
+                located(528_000_500),
+            ],
+        );
+        assert_eq!(fixed.len(), 1);
+        assert_eq!(fixed[0].address.city, "Berlin");
+        assert_eq!(fixed[0].address.postcode, "10115");
+        assert_eq!(fixed[0].confidence, 0.8);
+        assert!(fixed[0]
+            .inferred
+            .iter()
+            .all(|(_, method)| *method == Method::Neighbours));
+        let reasons: Vec<&str> = unfixable.iter().map(|(_, reason)| reason.kind()).collect();
+        assert_eq!(
+            reasons,
+            vec!["neighbours_disagree", "no_neighbours", "neighbours_disagree"]
+        );
+    }
+}
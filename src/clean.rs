@@ -0,0 +1,297 @@
+//! Rule based cleaning of junk tag values, like cities named `"<format"` or `1,2,3`,
+//! or quoted house numbers. Rejected values are dropped, so the autofixer can try to fill them in,
+//! and can be written to a file to fix them in OpenStreetMap.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use log::info;
+use osmpbfreader::OsmId;
+use serde::Serialize;
+
+use crate::parse::IncompleteAddress;
+
+const QUOTES: &[char] = &['"', '\'', '“', '”', '„', '«', '»', '`'];
+
+/// Strip quotes around the value, but keep a single one like in `'s-Hertogenbosch`.
+fn unquote(mut value: &str) -> &str {
+    while let (Some(first), Some(last)) = (value.chars().next(), value.chars().last()) {
+        if value.chars().count() < 2 || !QUOTES.contains(&first) || !QUOTES.contains(&last) {
+            break;
+        }
+        value = value[first.len_utf8()..value.len() - last.len_utf8()].trim();
+    }
+    value
+}
+
+/// A value dropped by a cleaning rule.
+#[derive(Debug, Serialize)]
+struct Junk<'a> {
+    #[serde(with = "crate::osm_id::option")]
+    id: Option<OsmId>,
+    field: &'static str,
+    value: &'a str,
+    rule: &'static str,
+}
+
+/// The trimmed values of a `;` separated list, a value without `;` as is.
+fn split(value: &Option<String>) -> Vec<Option<String>> {
+    match value {
+        Some(value) if value.contains(';') => value
+            .split(';')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| Some(v.to_string()))
+            .collect(),
+        _ => vec![value.clone()],
+    }
+}
+
+#[derive(Default)]
+pub struct Cleaner {
+    /// How often each rule applied.
+    counts: BTreeMap<&'static str, usize>,
+    junk: Option<BufWriter<File>>,
+}
+
+impl Cleaner {
+    /// Write every rejected value to `path` as json lines.
+    pub fn junk_file(mut self, path: &Path) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("Could not create junk file {:?}: {}", path, e))?;
+        self.junk = Some(BufWriter::new(file));
+        Ok(self)
+    }
+
+    fn count(&mut self, rule: &'static str) {
+        *self.counts.entry(rule).or_default() += 1;
+    }
+
+    fn reject(
+        &mut self,
+        id: Option<OsmId>,
+        field: &'static str,
+        value: &str,
+        rule: &'static str,
+    ) -> Result<(), String> {
+        self.count(rule);
+        if let Some(junk) = &mut self.junk {
+            let junk_value = Junk {
+                id,
+                field,
+                value,
+                rule,
+            };
+            serde_json::to_writer(&mut *junk, &junk_value).map_err(|e| e.to_string())?;
+            junk.write_all("\n".as_bytes()).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Trim and unquote a value, or reject it.
+    /// `;` separated values are only allowed if `multi` is set, and are returned trimmed.
+    fn clean_value(
+        &mut self,
+        id: Option<OsmId>,
+        field: &'static str,
+        value: Option<String>,
+        multi: bool,
+    ) -> Result<Option<String>, String> {
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let trimmed = value.trim();
+        if trimmed.len() != value.len() {
+            self.count("trimmed");
+        }
+        let unquoted = unquote(trimmed);
+        if unquoted.len() != trimmed.len() {
+            self.count("quotes");
+        }
+        let rule = if !unquoted.chars().any(char::is_alphanumeric) {
+            Some("punctuation")
+        } else if unquoted.contains(['<', '>', '{', '}']) {
+            Some("markup")
+        } else if !multi && unquoted.contains(';') {
+            Some("multi_value")
+        } else if field == "city" && !unquoted.chars().any(char::is_alphabetic) {
+            Some("numeric_city")
+        } else {
+            None
+        };
+        match rule {
+            Some(rule) => {
+                self.reject(id, field, &value, rule)?;
+                Ok(None)
+            }
+            None => Ok(Some(unquoted.to_string())),
+        }
+    }
+
+    /// The values of a `;` separated list, each cleaned on its own, without the rejected ones.
+    /// `None` if all of them were rejected.
+    fn clean_values(
+        &mut self,
+        id: Option<OsmId>,
+        field: &'static str,
+        value: &Option<String>,
+    ) -> Result<Vec<Option<String>>, String> {
+        let values = split(value);
+        if values.len() == 1 {
+            return Ok(values);
+        }
+        let mut cleaned = Vec::new();
+        for value in values {
+            if let Some(value) = self.clean_value(id, field, value, false)? {
+                cleaned.push(Some(value));
+            }
+        }
+        if cleaned.is_empty() {
+            cleaned.push(None);
+        }
+        Ok(cleaned)
+    }
+
+    /// The cleaned address, or one address per value of `;` separated lists in the city,
+    /// postal code, street and house number. Streets and house numbers of the same count
+    /// are paired, like `Hauptstraße;Bahnhofstraße` and `1;2` of a corner building,
+    /// all other combinations are crossed.
+    pub fn clean(&mut self, a: IncompleteAddress) -> Result<Vec<IncompleteAddress>, String> {
+        let id = a.id;
+        let a = IncompleteAddress {
+            country: self.clean_value(id, "country", a.country, false)?,
+            city: self.clean_value(id, "city", a.city, true)?,
+            district: self.clean_value(id, "district", a.district, false)?,
+            postcode: self.clean_value(id, "postcode", a.postcode, true)?,
+            street: self.clean_value(id, "street", a.street, true)?,
+            housenumber: self.clean_value(id, "housenumber", a.housenumber, true)?,
+            unit: self.clean_value(id, "unit", a.unit, false)?,
+            ..a
+        };
+        let streets = self.clean_values(id, "street", &a.street)?;
+        let housenumbers = self.clean_values(id, "housenumber", &a.housenumber)?;
+        let buildings: Vec<(Option<String>, Option<String>)> =
+            match streets.len() == housenumbers.len() {
+                true => streets.into_iter().zip(housenumbers).collect(),
+                false => streets
+                    .iter()
+                    .flat_map(|st| housenumbers.iter().map(|hn| (st.clone(), hn.clone())))
+                    .collect(),
+            };
+        let cities = self.clean_values(id, "city", &a.city)?;
+        let postcodes = self.clean_values(id, "postcode", &a.postcode)?;
+        if cities.len() * postcodes.len() * buildings.len() == 1 {
+            let (street, housenumber) = buildings.into_iter().next().unwrap();
+            return Ok(vec![IncompleteAddress {
+                city: cities.into_iter().next().unwrap(),
+                postcode: postcodes.into_iter().next().unwrap(),
+                street,
+                housenumber,
+                ..a
+            }]);
+        }
+        self.count("multi_value_split");
+        let mut addresses = Vec::new();
+        for city in cities.iter() {
+            for postcode in postcodes.iter() {
+                for (street, housenumber) in buildings.iter() {
+                    addresses.push(IncompleteAddress {
+                        city: city.clone(),
+                        postcode: postcode.clone(),
+                        street: street.clone(),
+                        housenumber: housenumber.clone(),
+                        ..a.clone()
+                    });
+                }
+            }
+        }
+        Ok(addresses)
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        for (rule, count) in self.counts.iter() {
+            info!("Cleaning rule {} applied {} times.", rule, count);
+        }
+        if let Some(junk) = &mut self.junk {
+            junk.flush().map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(city: &str, housenumber: &str) -> IncompleteAddress {
+        IncompleteAddress {
+            country: Some("DE".into()),
+            city: Some(city.into()),
+            postcode: Some("10115".into()),
+            street: Some("Invalidenstraße".into()),
+            housenumber: Some(housenumber.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn junk_values() {
+        let mut cleaner = Cleaner::default();
+        let cleaned = cleaner.clean(address(" \"Berlin\"", "'5'")).unwrap();
+        assert_eq!(cleaned[0].city.as_deref(), Some("Berlin"));
+        assert_eq!(cleaned[0].housenumber.as_deref(), Some("5"));
+        let cleaned = cleaner.clean(address("'s-Hertogenbosch", "5")).unwrap();
+        assert_eq!(cleaned[0].city.as_deref(), Some("'s-Hertogenbosch"));
+        for city in ["<format", "1,2,3", "--"] {
+            let cleaned = cleaner.clean(address(city, "5")).unwrap();
+            assert_eq!(cleaned[0].city, None, "{}", city);
+        }
+        let cleaned = cleaner.clean(address("Berlin", "1; 2;3")).unwrap();
+        let housenumbers: Vec<&str> = cleaned
+            .iter()
+            .filter_map(|a| a.housenumber.as_deref())
+            .collect();
+        assert_eq!(housenumbers, vec!["1", "2", "3"]);
+        let cleaned = cleaner
+            .clean(IncompleteAddress {
+                street: Some("Invalidenstraße; Chausseestraße".into()),
+                ..address("Berlin;Mitte", "1;2")
+            })
+            .unwrap();
+        let addresses: Vec<(&str, &str, &str)> = cleaned
+            .iter()
+            .filter_map(|a| {
+                Some((
+                    a.city.as_deref()?,
+                    a.street.as_deref()?,
+                    a.housenumber.as_deref()?,
+                ))
+            })
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("Berlin", "Invalidenstraße", "1"),
+                ("Berlin", "Chausseestraße", "2"),
+                ("Mitte", "Invalidenstraße", "1"),
+                ("Mitte", "Chausseestraße", "2"),
+            ]
+        );
+        // Values of lists are cleaned on their own.
+        let cleaned = cleaner.clean(address("Berlin;12345", "--;'7'")).unwrap();
+        assert_eq!(cleaned.len(), 1);
+        assert_eq!(cleaned[0].city.as_deref(), Some("Berlin"));
+        assert_eq!(cleaned[0].housenumber.as_deref(), Some("7"));
+        let cleaned = cleaner.clean(address("\"Mitte\"; --", "8")).unwrap();
+        assert_eq!(cleaned[0].city.as_deref(), Some("Mitte"));
+        let cleaned = cleaner.clean(address("10115;--", "9")).unwrap();
+        assert_eq!(cleaned[0].city, None);
+        assert_eq!(cleaner.counts["numeric_city"], 3);
+        assert_eq!(cleaner.counts["punctuation"], 3);
+        assert_eq!(cleaner.counts["multi_value_split"], 2);
+    }
+}
@@ -1,38 +1,80 @@
 use std::{
+    borrow::Cow,
     cmp::Ordering,
-    collections::HashSet,
-    io::{self, BufRead, BufReader, Read},
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::mpsc::{sync_channel, SyncSender},
 };
 
 use codes_iso_3166::part_1::{CountryCode, ALL_CODES};
 use log::info;
+use osmpbfreader::OsmId;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    autofix::{try_autofixing},
+    abbreviations::Abbreviations,
+    autofix::{
+        try_autofixing, AutofixIndex, AutofixOptions, Field, Located, Locations, Method, Unfixable,
+    },
+    clean::Cleaner,
+    external_sort::ExternalSorter,
+    front_coded::FrontCoded,
     parse::{Address, IncompleteAddress},
+    postcode,
     sorted_vec::SortedVec,
 };
 
 pub fn iter_items(io: impl Read) -> impl Iterator<Item = Result<IncompleteAddress, String>> {
     let buf_reader = BufReader::new(io);
     buf_reader.lines().map(|line| match line {
-        Ok(text) => match serde_json::from_str::<IncompleteAddress>(text.as_str()) {
-            Ok(addr) => Ok(normalize_address(addr)),
-            Err(e) => Err(e.to_string()),
-        },
+        Ok(text) => {
+            serde_json::from_str::<IncompleteAddress>(text.as_str()).map_err(|e| e.to_string())
+        }
         Err(e) => Err(e.to_string()),
     })
 }
 
-fn normalize_address(a: IncompleteAddress) -> IncompleteAddress {
+/// Invalid postal codes are dropped, so the autofixer can look them up,
+/// and counted per country in `invalid_postcodes`.
+fn normalize_address(
+    a: IncompleteAddress,
+    invalid_postcodes: &mut BTreeMap<String, usize>,
+) -> IncompleteAddress {
+    // Postal code formats and abbreviations are keyed by uppercase country codes.
+    let country = a
+        .country
+        .map(|country| autocorrect_country_code(country).to_uppercase());
+    let postcode = match (&country, a.postcode) {
+        (Some(country), Some(postcode)) => {
+            let normalized = postcode::normalize(country, &postcode);
+            if normalized.is_none() {
+                *invalid_postcodes.entry(country.clone()).or_default() += 1;
+            }
+            normalized
+        }
+        (_, postcode) => postcode,
+    };
+    IncompleteAddress {
+        country,
+        postcode,
+        ..a
+    }
+}
+
+/// Expand abbreviations in the street and city name, if the country is known.
+fn expand_abbreviations(a: IncompleteAddress, abbreviations: &Abbreviations) -> IncompleteAddress {
+    let country = match &a.country {
+        Some(country) => country.clone(),
+        None => return a,
+    };
     IncompleteAddress {
-        country: a.country.map(autocorrect_country_code),
-        city: a.city,
-        postcode: a.postcode,
-        street: a.street,
-        housenumber: a.housenumber,
+        street: a.street.map(|s| abbreviations.expand(&country, &s, false)),
+        city: a.city.map(|c| abbreviations.expand(&country, &c, false)),
+        ..a
     }
 }
 
@@ -59,16 +101,107 @@ fn num_compressable(a: &str) -> bool {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum Housenumber {
     CleanInt(u16),
     Index(u32),
 }
 
+impl Housenumber {
+    /// Translate an index into another world's house number pool.
+    fn remap(self, hn_map: &[u32]) -> Self {
+        match self {
+            Housenumber::CleanInt(i) => Housenumber::CleanInt(i),
+            Housenumber::Index(i) => Housenumber::Index(hn_map[i as usize]),
+        }
+    }
+}
+
+/// House numbers are ordered by their text, so `10` comes before `9`.
+fn compare_housenumbers(pool: &SortedVec<String>, a: &Housenumber, b: &Housenumber) -> Ordering {
+    let text = |hn: &Housenumber| match hn {
+        Housenumber::CleanInt(v) => Cow::Owned(v.to_string()),
+        Housenumber::Index(i) => Cow::Borrowed(
+            pool.get(*i as usize)
+                .expect("Housenumber index greater then housenumber list length")
+                .as_str(),
+        ),
+    };
+    match (a, b) {
+        (Housenumber::Index(a), Housenumber::Index(b)) => a.cmp(b),
+        (a, b) => text(a).cmp(&text(b)),
+    }
+}
+
+/// Whether an address was tagged completely in OSM, or which fields the autofixer inferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Bit set of `INFERRED_*`.
+    inferred: u8,
+    /// Confidence of the inference in percent.
+    confidence: u8,
+}
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Self::ORIGINAL
+    }
+}
+
+impl Provenance {
+    pub const ORIGINAL: Provenance = Provenance {
+        inferred: 0,
+        confidence: 100,
+    };
+    pub const INFERRED_COUNTRY: u8 = 1;
+    pub const INFERRED_CITY: u8 = 2;
+    pub const INFERRED_ZIP: u8 = 4;
+    /// Inferred from the locations of neighbouring addresses.
+    pub const INTERPOLATED: u8 = 8;
+
+    pub fn inferred(inferred: u8, confidence: f64) -> Self {
+        Self {
+            inferred,
+            confidence: (confidence.clamp(0., 1.) * 100.).round() as u8,
+        }
+    }
+    pub fn is_original(&self) -> bool {
+        self.inferred == 0
+    }
+    pub fn confidence(&self) -> f64 {
+        self.confidence as f64 / 100.
+    }
+    /// E.g. `["inferred-city", "inferred-zip"]`, or `["original"]`.
+    pub fn flags(&self) -> Vec<&'static str> {
+        if self.is_original() {
+            return vec!["original"];
+        }
+        [
+            (Self::INFERRED_COUNTRY, "inferred-country"),
+            (Self::INFERRED_CITY, "inferred-city"),
+            (Self::INFERRED_ZIP, "inferred-zip"),
+            (Self::INTERPOLATED, "interpolated"),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| self.inferred & flag != 0)
+        .map(|(_, name)| name)
+        .collect()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Street {
     index: u32,
     housenumbers: Vec<Housenumber>,
+    /// OSM object each house number originates from, in the same order.
+    /// Empty if no ids are known for this street.
+    osm_ids: Vec<Option<OsmId>>,
+    /// Units (flats, apartments) of each house number as indexes into the unit pool,
+    /// in the same order. Empty if no units are known for this street.
+    units: Vec<Vec<u32>>,
+    /// How each house number was completed, in the same order.
+    /// Empty if all house numbers of this street are tagged completely in OSM.
+    provenance: Vec<Provenance>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -77,23 +210,118 @@ pub struct PostalArea {
     streets: Vec<Street>,
 }
 
+/// Suburb or district of a city, `addr:suburb` or `addr:district`.
+/// Addresses without one are kept in a district with an empty name.
 #[derive(Serialize, Deserialize)]
-pub struct City {
+pub struct District {
     pub name: String,
     areas: Vec<PostalArea>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct City {
+    pub name: String,
+    /// Names in other languages, as (language code, name).
+    names: Vec<(String, String)>,
+    districts: Vec<District>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Country {
     pub code: String,
     cities: Vec<City>,
 }
 
+/// Street names of a world, see [World::country_street_pools].
+#[derive(Serialize, Deserialize)]
+enum StreetPool {
+    /// Sorted names of all countries, as built.
+    Global(SortedVec<String>),
+    /// A front coded pool per country, in the order of the countries, with the index
+    /// of its first name. The streets of a country index into its part of the concatenated pools.
+    PerCountry(Vec<(u32, FrontCoded)>),
+}
+
+impl StreetPool {
+    fn get(&self, index: u32) -> Cow<'_, str> {
+        match self {
+            StreetPool::Global(pool) => Cow::Borrowed(pool[index as usize].as_str()),
+            StreetPool::PerCountry(pools) => {
+                let (first, pool) = &pools[pools.partition_point(|(first, _)| *first <= index) - 1];
+                Cow::Owned(
+                    pool.get((index - first) as usize)
+                        .expect("Street index greater then street pool length"),
+                )
+            }
+        }
+    }
+    /// All names in the order of their indexes.
+    fn names(&self) -> Vec<Cow<'_, str>> {
+        match self {
+            StreetPool::Global(pool) => pool.iter().map(|s| Cow::Borrowed(s.as_str())).collect(),
+            StreetPool::PerCountry(pools) => pools
+                .iter()
+                .flat_map(|(_, pool)| pool.iter())
+                .map(Cow::Owned)
+                .collect(),
+        }
+    }
+}
+
+/// Start of every world file, followed by [WORLD_FORMAT_VERSION].
+const WORLD_MAGIC: &[u8; 4] = b"MACS";
+/// Version of the serialized layout of [World], to be increased whenever it changes.
+const WORLD_FORMAT_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize)]
 pub struct World {
-    unique_streets: SortedVec<String>,
+    streets: StreetPool,
     housenumbers: SortedVec<String>,
+    units: SortedVec<String>,
     countries: Vec<Country>,
+    keep_osm_ids: bool,
+    /// Street names in other languages, as (language code, index into `streets`),
+    /// keyed by the index of the street name used in the address tree.
+    street_names: BTreeMap<u32, Vec<(String, u32)>>,
+    /// Applied to street and city names before inserting, and to queries.
+    abbreviations: Abbreviations,
+}
+
+/// Move the elements of `v` into the order given by indexes into `v`.
+fn permute<T>(v: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut slots: Vec<Option<T>> = v.into_iter().map(Some).collect();
+    order
+        .iter()
+        .map(|i| slots[*i].take().expect("order contained an index twice"))
+        .collect()
+}
+
+/// Position of an element in a sorted slice, like [slice::binary_search_by].
+/// The last element is checked first, as addresses are mostly inserted in sorted order.
+fn sorted_position<T>(v: &[T], mut cmp: impl FnMut(&T) -> Ordering) -> Result<usize, usize> {
+    match v.last().map(&mut cmp) {
+        None | Some(Ordering::Less) => Err(v.len()),
+        Some(Ordering::Equal) => Ok(v.len() - 1),
+        Some(Ordering::Greater) => v.binary_search_by(cmp),
+    }
+}
+
+/// The element of `v` with the given key, inserted with `new` if missing.
+/// `v` is kept sorted by the key, so building a world needs no linear searches.
+fn find_or_insert<'a, T, K: Ord + ?Sized>(
+    v: &'a mut Vec<T>,
+    key: &K,
+    key_of: impl Fn(&T) -> &K,
+    new: impl FnOnce() -> T,
+) -> &'a mut T {
+    let i = match sorted_position(v, |e| key_of(e).cmp(key)) {
+        Ok(i) => i,
+        Err(i) => {
+            v.insert(i, new());
+            i
+        }
+    };
+    &mut v[i]
 }
 
 impl Street {
@@ -101,29 +329,161 @@ impl Street {
         Self {
             index,
             housenumbers: Vec::new(),
+            osm_ids: Vec::new(),
+            units: Vec::new(),
+            provenance: Vec::new(),
+        }
+    }
+    /// Insert a house number, or only the unit if the house number is already known.
+    /// A house number tagged completely in OSM replaces an inferred one.
+    /// House numbers are kept sorted by `hn_cmp`, units by their index.
+    pub fn insert_housenumber(
+        &mut self,
+        hn: Housenumber,
+        osm_id: Option<OsmId>,
+        unit: Option<u32>,
+        provenance: Provenance,
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        let i = match sorted_position(&self.housenumbers, |e| hn_cmp(e, &hn)) {
+            Ok(i) => i,
+            Err(i) => {
+                if osm_id.is_some() && self.osm_ids.len() < self.housenumbers.len() {
+                    self.osm_ids.resize(self.housenumbers.len(), None);
+                }
+                if !provenance.is_original() && self.provenance.len() < self.housenumbers.len() {
+                    self.provenance
+                        .resize(self.housenumbers.len(), Provenance::ORIGINAL);
+                }
+                self.housenumbers.insert(i, hn);
+                if !self.osm_ids.is_empty() || osm_id.is_some() {
+                    self.osm_ids.insert(i, osm_id);
+                }
+                if !self.units.is_empty() {
+                    self.units.insert(i, Vec::new());
+                }
+                if !self.provenance.is_empty() || !provenance.is_original() {
+                    self.provenance.insert(i, provenance);
+                }
+                i
+            }
+        };
+        if provenance.is_original() && i < self.provenance.len() {
+            self.provenance[i] = provenance;
+        }
+        if let Some(unit) = unit {
+            if self.units.len() < self.housenumbers.len() {
+                self.units.resize(self.housenumbers.len(), Vec::new());
+            }
+            if let Err(j) = self.units[i].binary_search(&unit) {
+                self.units[i].insert(j, unit);
+            }
         }
     }
-    pub fn insert_housenumber(&mut self, hn: Housenumber) {
-        if !self.housenumbers.contains(&hn) {
-            self.housenumbers.push(hn);
+    pub fn sort_with(&mut self, mut hn_sort: impl FnMut(&Housenumber, &Housenumber) -> Ordering) {
+        if self.osm_ids.is_empty() && self.units.is_empty() && self.provenance.is_empty() {
+            self.housenumbers.sort_by(hn_sort);
+            return;
+        }
+        let mut order: Vec<usize> = (0..self.housenumbers.len()).collect();
+        order.sort_by(|a, b| hn_sort(&self.housenumbers[*a], &self.housenumbers[*b]));
+        self.housenumbers = permute(std::mem::take(&mut self.housenumbers), &order);
+        if !self.osm_ids.is_empty() {
+            self.osm_ids = permute(std::mem::take(&mut self.osm_ids), &order);
+        }
+        if !self.provenance.is_empty() {
+            self.provenance = permute(std::mem::take(&mut self.provenance), &order);
         }
+        if !self.units.is_empty() {
+            self.units = permute(std::mem::take(&mut self.units), &order);
+            // The unit pool is sorted, so are the indexes into it.
+            for units in self.units.iter_mut() {
+                units.sort();
+            }
+        }
+    }
+    fn merge(
+        &mut self,
+        other: Street,
+        hn_map: &[u32],
+        unit_map: &[u32],
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        let osm_ids = other.osm_ids.into_iter().chain(std::iter::repeat(None));
+        let units = other.units.into_iter().chain(std::iter::repeat(Vec::new()));
+        let provenance = other
+            .provenance
+            .into_iter()
+            .chain(std::iter::repeat(Provenance::ORIGINAL));
+        let details = osm_ids.zip(units).zip(provenance);
+        for (hn, ((osm_id, units), provenance)) in other.housenumbers.into_iter().zip(details) {
+            let hn = hn.remap(hn_map);
+            match units.is_empty() {
+                true => self.insert_housenumber(hn, osm_id, None, provenance, &hn_cmp),
+                false => {
+                    for unit in units {
+                        let unit = Some(unit_map[unit as usize]);
+                        self.insert_housenumber(hn, osm_id, unit, provenance, &hn_cmp);
+                    }
+                }
+            }
+        }
+    }
+    pub fn name<'a>(&self, w: &'a World) -> Cow<'a, str> {
+        w.streets.get(self.index)
+    }
+    /// Name in the given language, the default name if unknown.
+    pub fn name_in<'a>(&self, lang: Option<&str>, w: &'a World) -> Cow<'a, str> {
+        w.street_name_in(self.index, lang)
     }
-    pub fn sort_with(&mut self, hn_sort: impl FnMut(&Housenumber, &Housenumber) -> Ordering) {
-        self.housenumbers.sort_by(hn_sort)
+    fn has_name(&self, name: &str, w: &World) -> bool {
+        w.street_names_of(self.index)
+            .any(|n| n.to_lowercase() == name.to_lowercase())
     }
-    fn housenumber_iter<'a>(&'a self, w: &'a World) -> impl Iterator<Item = String> + 'a {
+    pub fn housenumber_count(&self) -> usize {
+        self.housenumbers.len()
+    }
+    pub fn housenumber_iter<'a>(&'a self, w: &'a World) -> impl Iterator<Item = String> + 'a {
         self.housenumbers.iter().map(|s| match s {
             Housenumber::Index(i) => w.housenumbers[*i as usize].to_string(),
             Housenumber::CleanInt(i) => i.to_string(),
         })
     }
-    pub fn iter_housenumbers_prefixed<'a>(
+    /// House numbers with their OSM id, if kept, and how they were completed.
+    pub fn iter_housenumber_details_prefixed<'a>(
         &'a self,
         prefix: String,
         world: &'a World,
-    ) -> impl Iterator<Item = String> + 'a {
+    ) -> impl Iterator<Item = (String, Option<OsmId>, Provenance)> + 'a {
+        let osm_ids = self.osm_ids.iter().cloned().chain(std::iter::repeat(None));
+        let provenance = self
+            .provenance
+            .iter()
+            .cloned()
+            .chain(std::iter::repeat(Provenance::ORIGINAL));
         self.housenumber_iter(world)
-            .filter(move |hn| hn.to_lowercase().starts_with(&prefix.to_lowercase()))
+            .zip(osm_ids)
+            .zip(provenance)
+            .map(|((hn, osm_id), provenance)| (hn, osm_id, provenance))
+            .filter(move |(hn, _, _)| hn.to_lowercase().starts_with(&prefix.to_lowercase()))
+    }
+    /// Units of a house number, `None` if the street does not contain the house number.
+    pub fn iter_units_prefixed<'a>(
+        &'a self,
+        housenumber: &str,
+        prefix: String,
+        world: &'a World,
+    ) -> Option<impl Iterator<Item = &'a String> + 'a> {
+        let i = self
+            .housenumber_iter(world)
+            .position(|hn| hn.to_lowercase() == housenumber.to_lowercase())?;
+        let units = self.units.get(i).map(|u| u.as_slice()).unwrap_or(&[]);
+        Some(
+            units
+                .iter()
+                .map(|u| &world.units[*u as usize])
+                .filter(move |unit| unit.to_lowercase().starts_with(&prefix.to_lowercase())),
+        )
     }
 }
 
@@ -134,15 +494,22 @@ impl PostalArea {
             streets: Vec::new(),
         }
     }
-    pub fn insert_address(&mut self, street_index: u32, hn: Housenumber) {
-        let street_mut = self.streets.iter_mut().find(|e| e.index == street_index);
-        if let Some(street) = street_mut {
-            street.insert_housenumber(hn);
-        } else {
-            let mut street = Street::new(street_index);
-            street.insert_housenumber(hn);
-            self.streets.push(street);
-        }
+    pub fn insert_address(
+        &mut self,
+        street_index: u32,
+        hn: Housenumber,
+        osm_id: Option<OsmId>,
+        unit: Option<u32>,
+        provenance: Provenance,
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        find_or_insert(
+            &mut self.streets,
+            &street_index,
+            |e| &e.index,
+            || Street::new(street_index),
+        )
+        .insert_housenumber(hn, osm_id, unit, provenance, hn_cmp);
     }
     pub fn sort_with(&mut self, mut hn_sort: impl FnMut(&Housenumber, &Housenumber) -> Ordering) {
         self.streets.sort_by(|a, b| a.index.cmp(&b.index));
@@ -150,39 +517,81 @@ impl PostalArea {
             street.sort_with(&mut hn_sort)
         }
     }
+    fn merge(
+        &mut self,
+        other: PostalArea,
+        street_map: &[u32],
+        hn_map: &[u32],
+        unit_map: &[u32],
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        for street in other.streets {
+            let street_index = street_map[street.index as usize];
+            find_or_insert(
+                &mut self.streets,
+                &street_index,
+                |e| &e.index,
+                || Street::new(street_index),
+            )
+            .merge(street, hn_map, unit_map, &hn_cmp);
+        }
+    }
+    /// Streets with any name starting with `prefix`, named in the given language if known.
     pub fn iter_streets_prefixed<'a>(
         &'a self,
         prefix: String,
+        lang: Option<&'a str>,
         world: &'a World,
-    ) -> impl Iterator<Item = &'a String> {
+    ) -> impl Iterator<Item = Cow<'a, str>> {
         self.streets
             .iter()
-            .map(|s| &world.unique_streets[s.index as usize])
-            .filter(move |street| street.to_lowercase().starts_with(&prefix.to_lowercase()))
+            .filter(move |s| {
+                world
+                    .street_names_of(s.index)
+                    .any(|street| street.to_lowercase().starts_with(&prefix.to_lowercase()))
+            })
+            .map(move |s| s.name_in(lang, world))
+    }
+    pub fn iter_streets(&self) -> impl Iterator<Item = &Street> {
+        self.streets.iter()
     }
     pub fn get_street<'a>(&'a self, street: &str, world: &'a World) -> Option<&Street> {
-        self.streets.iter().find(|s| {
-            &world.unique_streets[s.index as usize].to_lowercase() == &street.to_lowercase()
-        })
+        self.streets.iter().find(|s| s.has_name(street, world))
+    }
+    /// The street of the given index into the street pool, see [World::street_index].
+    pub fn get_street_by_index(&self, index: u32) -> Option<&Street> {
+        let i = self
+            .streets
+            .binary_search_by_key(&index, |s| s.index)
+            .ok()?;
+        Some(&self.streets[i])
     }
 }
 
-impl City {
+impl District {
     pub fn new(name: String) -> Self {
         Self {
             name,
             areas: Vec::new(),
         }
     }
-    pub fn insert_address(&mut self, postal_code: String, street_index: u32, hn: Housenumber) {
-        let post_mut = self.areas.iter_mut().find(|e| e.code == postal_code);
-        if let Some(area) = post_mut {
-            area.insert_address(street_index, hn);
-        } else {
-            let mut area: PostalArea = PostalArea::new(postal_code);
-            area.insert_address(street_index, hn);
-            self.areas.push(area);
-        }
+    pub fn insert_address(
+        &mut self,
+        postal_code: String,
+        street_index: u32,
+        hn: Housenumber,
+        osm_id: Option<OsmId>,
+        unit: Option<u32>,
+        provenance: Provenance,
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        find_or_insert(
+            &mut self.areas,
+            postal_code.as_str(),
+            |e| e.code.as_str(),
+            || PostalArea::new(postal_code.clone()),
+        )
+        .insert_address(street_index, hn, osm_id, unit, provenance, hn_cmp);
     }
     pub fn sort_with(&mut self, mut hn_sort: impl FnMut(&Housenumber, &Housenumber) -> Ordering) {
         self.areas.sort_by(|a, b| a.code.cmp(&b.code));
@@ -190,11 +599,23 @@ impl City {
             area.sort_with(&mut hn_sort)
         }
     }
-    pub fn iter_zips_prefixed(&self, prefix: String) -> impl Iterator<Item = &String> {
-        self.areas
-            .iter()
-            .filter(move |c| c.code.to_lowercase().starts_with(&prefix.to_lowercase()))
-            .map(|c| &c.code)
+    fn merge(
+        &mut self,
+        other: District,
+        street_map: &[u32],
+        hn_map: &[u32],
+        unit_map: &[u32],
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        for area in other.areas {
+            find_or_insert(
+                &mut self.areas,
+                area.code.as_str(),
+                |e| e.code.as_str(),
+                || PostalArea::new(area.code.clone()),
+            )
+            .merge(area, street_map, hn_map, unit_map, &hn_cmp);
+        }
     }
     pub fn get_postal_area(&self, zip: &str) -> Option<&PostalArea> {
         self.areas
@@ -207,6 +628,141 @@ impl City {
     }
 }
 
+impl City {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            names: Vec::new(),
+            districts: Vec::new(),
+        }
+    }
+    pub fn insert_name(&mut self, lang: String, name: String) {
+        if !self.names.iter().any(|(l, _)| l == &lang) {
+            self.names.push((lang, name));
+        }
+    }
+    /// Name in the given language, the default name if unknown.
+    pub fn name_in(&self, lang: Option<&str>) -> &String {
+        lang.and_then(|lang| self.names.iter().find(|(l, _)| l == lang))
+            .map(|(_, name)| name)
+            .unwrap_or(&self.name)
+    }
+    /// The name and the names in other languages.
+    pub fn iter_names(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.name).chain(self.names.iter().map(|(_, name)| name))
+    }
+    pub fn insert_address(
+        &mut self,
+        district: String,
+        postal_code: String,
+        street_index: u32,
+        hn: Housenumber,
+        osm_id: Option<OsmId>,
+        unit: Option<u32>,
+        provenance: Provenance,
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        find_or_insert(
+            &mut self.districts,
+            district.as_str(),
+            |e| e.name.as_str(),
+            || District::new(district.clone()),
+        )
+        .insert_address(
+            postal_code,
+            street_index,
+            hn,
+            osm_id,
+            unit,
+            provenance,
+            hn_cmp,
+        );
+    }
+    pub fn sort_with(&mut self, mut hn_sort: impl FnMut(&Housenumber, &Housenumber) -> Ordering) {
+        self.districts.sort_by(|a, b| a.name.cmp(&b.name));
+        for district in self.districts.iter_mut() {
+            district.sort_with(&mut hn_sort)
+        }
+    }
+    fn merge(
+        &mut self,
+        other: City,
+        street_map: &[u32],
+        hn_map: &[u32],
+        unit_map: &[u32],
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        for (lang, name) in other.names {
+            self.insert_name(lang, name);
+        }
+        for district in other.districts {
+            find_or_insert(
+                &mut self.districts,
+                district.name.as_str(),
+                |e| e.name.as_str(),
+                || District::new(district.name.clone()),
+            )
+            .merge(district, street_map, hn_map, unit_map, &hn_cmp);
+        }
+    }
+    /// Named districts starting with `prefix`.
+    pub fn iter_districts_prefixed(&self, prefix: String) -> impl Iterator<Item = &String> {
+        self.districts
+            .iter()
+            .filter(|d| !d.name.is_empty())
+            .filter(move |d| d.name.to_lowercase().starts_with(&prefix.to_lowercase()))
+            .map(|d| &d.name)
+    }
+    pub fn get_district(&self, district: &str) -> Option<&District> {
+        self.districts
+            .binary_search_by_key(&district.to_lowercase(), |d| d.name.to_lowercase())
+            .ok()
+            .map(|i| &self.districts[i])
+    }
+    pub fn iter_districts(&self) -> impl Iterator<Item = &District> {
+        self.districts.iter()
+    }
+    /// Postal codes of all districts, or only the given one, sorted and without duplicates.
+    pub fn iter_zips_prefixed(
+        &self,
+        prefix: String,
+        district: Option<&str>,
+    ) -> impl Iterator<Item = &String> {
+        let codes: BTreeSet<&String> = self
+            .districts
+            .iter()
+            .filter(|d| match district {
+                Some(district) => d.name.to_lowercase() == district.to_lowercase(),
+                None => true,
+            })
+            .flat_map(|d| d.iter_zips())
+            .map(|area| &area.code)
+            .filter(|code| code.to_lowercase().starts_with(&prefix.to_lowercase()))
+            .collect();
+        codes.into_iter()
+    }
+    /// The postal area with the given code in every district, or only in the given one.
+    /// A postal code might span multiple districts.
+    pub fn get_postal_areas(&self, zip: &str, district: Option<&str>) -> Vec<&PostalArea> {
+        match district {
+            Some(district) => self
+                .get_district(district)
+                .and_then(|d| d.get_postal_area(zip))
+                .into_iter()
+                .collect(),
+            None => self
+                .districts
+                .iter()
+                .filter_map(|d| d.get_postal_area(zip))
+                .collect(),
+        }
+    }
+    /// Postal areas of all districts.
+    pub fn iter_zips(&self) -> impl Iterator<Item = &PostalArea> {
+        self.districts.iter().flat_map(|d| d.iter_zips())
+    }
+}
+
 impl Country {
     pub fn new(code: String) -> Self {
         Self {
@@ -217,18 +773,31 @@ impl Country {
     pub fn insert_address(
         &mut self,
         city: String,
+        district: String,
         postal_code: String,
         street_index: u32,
         hn: Housenumber,
+        osm_id: Option<OsmId>,
+        unit: Option<u32>,
+        provenance: Provenance,
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
     ) {
-        let city_mut = self.cities.iter_mut().find(|e| e.name == city);
-        if let Some(city) = city_mut {
-            city.insert_address(postal_code, street_index, hn);
-        } else {
-            let mut city = City::new(city);
-            city.insert_address(postal_code, street_index, hn);
-            self.cities.push(city);
-        }
+        find_or_insert(
+            &mut self.cities,
+            city.as_str(),
+            |e| e.name.as_str(),
+            || City::new(city.clone()),
+        )
+        .insert_address(
+            district,
+            postal_code,
+            street_index,
+            hn,
+            osm_id,
+            unit,
+            provenance,
+            hn_cmp,
+        );
     }
     pub fn sort_with(&mut self, mut hn_sort: impl FnMut(&Housenumber, &Housenumber) -> Ordering) {
         self.cities.sort_by(|a, b| a.name.cmp(&b.name));
@@ -236,89 +805,446 @@ impl Country {
             city.sort_with(&mut hn_sort)
         }
     }
-    pub fn iter_cities_prefixed(&self, prefix: String) -> impl Iterator<Item = &String> {
+    fn merge(
+        &mut self,
+        other: Country,
+        street_map: &[u32],
+        hn_map: &[u32],
+        unit_map: &[u32],
+        hn_cmp: impl Fn(&Housenumber, &Housenumber) -> Ordering,
+    ) {
+        for city in other.cities {
+            find_or_insert(
+                &mut self.cities,
+                city.name.as_str(),
+                |e| e.name.as_str(),
+                || City::new(city.name.clone()),
+            )
+            .merge(city, street_map, hn_map, unit_map, &hn_cmp);
+        }
+    }
+    /// Cities with any name starting with `prefix`, named in the given language if known.
+    pub fn iter_cities_prefixed<'a>(
+        &'a self,
+        prefix: String,
+        lang: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a String> {
         self.cities
             .iter()
-            .filter(move |c| c.name.to_lowercase().starts_with(&prefix.to_lowercase()))
-            .map(|c| &c.name)
+            .filter(move |c| {
+                c.iter_names()
+                    .any(|name| name.to_lowercase().starts_with(&prefix.to_lowercase()))
+            })
+            .map(move |c| c.name_in(lang))
     }
+    /// City by its name in any language.
     pub fn get_city(&self, city_name: &str) -> Option<&City> {
-        self.cities
-            .binary_search_by_key(&city_name.to_lowercase(), |c| c.name.to_lowercase())
-            .ok()
-            .map(|i| &self.cities[i])
+        let city_name = city_name.to_lowercase();
+        match self
+            .cities
+            .binary_search_by_key(&city_name, |c| c.name.to_lowercase())
+        {
+            Ok(i) => Some(&self.cities[i]),
+            Err(_) => self.cities.iter().find(|c| {
+                c.names
+                    .iter()
+                    .any(|(_, name)| name.to_lowercase() == city_name)
+            }),
+        }
     }
     pub fn iter_cities(&self) -> impl Iterator<Item = &City> {
         self.cities.iter()
     }
+    /// Add the name of a city in another language, inserting the city if not known yet.
+    fn insert_city_name(&mut self, city_name: &str, lang: String, name: String) {
+        find_or_insert(
+            &mut self.cities,
+            city_name,
+            |e| e.name.as_str(),
+            || City::new(city_name.to_string()),
+        )
+        .insert_name(lang, name);
+    }
 }
 
 impl World {
     pub fn new(unique_streets: SortedVec<String>, housenumbers: SortedVec<String>) -> Self {
         Self {
             housenumbers,
-            unique_streets,
+            streets: StreetPool::Global(unique_streets),
+            units: Vec::new().into(),
             countries: Vec::new(),
+            keep_osm_ids: false,
+            street_names: BTreeMap::new(),
+            abbreviations: Abbreviations::default(),
         }
     }
+    /// Abbreviations the inserted names have been expanded with.
+    pub fn abbreviations(mut self, abbreviations: Abbreviations) -> Self {
+        self.abbreviations = abbreviations;
+        self
+    }
+    /// Expand abbreviations in a street or city name of a query, see [Abbreviations::expand].
+    pub fn expand_abbreviations(&self, country_code: &str, text: &str, prefix: bool) -> String {
+        self.abbreviations.expand(country_code, text, prefix)
+    }
+    /// Pool of all units (flats, apartments) which will be inserted.
+    pub fn units(mut self, units: SortedVec<String>) -> Self {
+        self.units = units;
+        self
+    }
+    /// Store the OSM id of every inserted address, if known.
+    pub fn keep_osm_ids(mut self, keep: bool) -> Self {
+        self.keep_osm_ids = keep;
+        self
+    }
     pub fn insert_address(
         &mut self,
         country_code: String,
         city_name: String,
+        district: Option<String>,
         zip: String,
         street: String,
         housenumber: String,
+        osm_id: Option<OsmId>,
+        unit: Option<String>,
+        provenance: Provenance,
     ) {
-        let housenumber = match num_compressable(&housenumber) {
+        let district = district.unwrap_or_default();
+        let osm_id = osm_id.filter(|_| self.keep_osm_ids);
+        let (street_index, housenumber, unit) =
+            self.pool_indexes(&country_code, &street, &housenumber, unit);
+        let pool = &self.housenumbers;
+        find_or_insert(
+            &mut self.countries,
+            country_code.as_str(),
+            |e| e.code.as_str(),
+            || Country::new(country_code.clone()),
+        )
+        .insert_address(
+            city_name,
+            district,
+            zip,
+            street_index,
+            housenumber,
+            osm_id,
+            unit,
+            provenance,
+            |a, b| compare_housenumbers(pool, a, b),
+        );
+    }
+    /// Index of a street name, in the pool of the country if there is one per country.
+    pub fn street_index(&self, country_code: &str, name: &str) -> Option<usize> {
+        match &self.streets {
+            StreetPool::Global(pool) => pool.index_of(&name.to_string()),
+            StreetPool::PerCountry(pools) => {
+                // The pools are in the order of the countries.
+                let country = self
+                    .countries
+                    .binary_search_by(|c| c.code.as_str().cmp(country_code))
+                    .ok()?;
+                let (first, pool) = pools.get(country)?;
+                Some(*first as usize + pool.index_of(name)?)
+            }
+        }
+    }
+    /// Indexes of a street, house number and unit into the pools of this world.
+    fn pool_indexes(
+        &self,
+        country_code: &str,
+        street: &str,
+        housenumber: &str,
+        unit: Option<String>,
+    ) -> (u32, Housenumber, Option<u32>) {
+        let unit = unit.map(|unit| {
+            self.units
+                .index_of(&unit)
+                .expect("self.units did not contain inserted unit") as u32
+        });
+        let housenumber = match num_compressable(housenumber) {
             true => Housenumber::CleanInt(housenumber.parse().unwrap()),
             false => Housenumber::Index(
                 self.housenumbers
-                    .index_of(&housenumber)
+                    .index_of(&housenumber.to_string())
                     .expect("self.housenumbers did not contain inserted house number")
                     as u32,
             ),
         };
-        let street_index = self
-            .unique_streets
-            .index_of(&street)
-            .expect("self.unique_streets did no contain inserted street name")
-            as u32;
-        let country_mut = self.countries.iter_mut().find(|e| e.code == country_code);
-        if let Some(country) = country_mut {
-            country.insert_address(city_name, zip, street_index, housenumber);
-        } else {
-            let mut country = Country::new(country_code);
-            country.insert_address(city_name, zip, street_index, housenumber);
-            self.countries.push(country);
+        let street_index =
+            self.street_index(country_code, street)
+                .expect("self.streets did no contain inserted street name") as u32;
+        (street_index, housenumber, unit)
+    }
+    /// Build a country from its complete addresses, apart from this world,
+    /// so countries can be built in parallel. Street names in other languages are not inserted.
+    pub fn build_country(&self, code: String, addresses: impl Iterator<Item = Address>) -> Country {
+        let mut country = Country::new(code);
+        for addr in addresses {
+            for (lang, name) in addr.city_names {
+                country.insert_city_name(&addr.city, lang, name);
+            }
+            let (street_index, housenumber, unit) =
+                self.pool_indexes(&addr.country, &addr.street, &addr.housenumber, addr.unit);
+            country.insert_address(
+                addr.city,
+                addr.district.unwrap_or_default(),
+                addr.postcode,
+                street_index,
+                housenumber,
+                addr.id.filter(|_| self.keep_osm_ids),
+                unit,
+                Provenance::ORIGINAL,
+                |a, b| compare_housenumbers(&self.housenumbers, a, b),
+            );
         }
+        country
     }
-    pub fn sort(&mut self) {
-        self.countries.sort_by(|a, b| a.code.cmp(&b.code));
+    /// Add a country built with [World::build_country].
+    pub fn insert_country(&mut self, country: Country) -> Result<(), String> {
+        match self
+            .countries
+            .binary_search_by(|e| e.code.cmp(&country.code))
+        {
+            Ok(_) => Err(format!("Country {} was built twice", country.code)),
+            Err(i) => {
+                self.countries.insert(i, country);
+                Ok(())
+            }
+        }
+    }
+    /// Add the name of a city in another language, inserting the city if not known yet.
+    pub fn insert_city_name(
+        &mut self,
+        country_code: &str,
+        city_name: &str,
+        lang: String,
+        name: String,
+    ) {
+        find_or_insert(
+            &mut self.countries,
+            country_code,
+            |e| e.code.as_str(),
+            || Country::new(country_code.to_string()),
+        )
+        .insert_city_name(city_name, lang, name);
+    }
+    /// Add the name of a street in another language. Both names have to be in the street pool.
+    pub fn insert_street_name(
+        &mut self,
+        country_code: &str,
+        street: &str,
+        lang: String,
+        name: &str,
+    ) {
+        let index_of = |name: &str| {
+            self.street_index(country_code, name)
+                .expect("self.streets did no contain inserted street name") as u32
+        };
+        let (street, name) = (index_of(street), index_of(name));
+        let names = self.street_names.entry(street).or_default();
+        if !names.iter().any(|(l, _)| l == &lang) {
+            names.push((lang, name));
+        }
+    }
+    /// Name of a street in the given language, the default name if unknown.
+    pub fn street_name_in(&self, index: u32, lang: Option<&str>) -> Cow<'_, str> {
+        let translated = lang.and_then(|lang| {
+            self.street_names
+                .get(&index)?
+                .iter()
+                .find(|(l, _)| l == lang)
+        });
+        match translated {
+            Some((_, name)) => self.streets.get(*name),
+            None => self.streets.get(index),
+        }
+    }
+    /// The name of a street in all known languages, starting with the default name.
+    fn street_names_of(&self, index: u32) -> impl Iterator<Item = Cow<'_, str>> {
+        let translated = self.street_names.get(&index).into_iter().flatten();
+        std::iter::once(index)
+            .chain(translated.map(|(_, name)| *name))
+            .map(|i| self.streets.get(i))
+    }
+    /// Replace the global street pool by a front coded pool per country,
+    /// so the names of a country are close together and take less memory.
+    /// No addresses can be inserted afterwards.
+    pub fn country_street_pools(&mut self) {
+        let global = match &self.streets {
+            StreetPool::Global(pool) => pool,
+            StreetPool::PerCountry(_) => return,
+        };
+        let mut pools: Vec<(u32, FrontCoded)> = Vec::new();
+        let mut street_names: BTreeMap<u32, Vec<(String, u32)>> = BTreeMap::new();
+        let mut first = 0;
         for country in self.countries.iter_mut() {
-            country.sort_with(|hn_a: &Housenumber, hn_b: &Housenumber| {
-                let a = match hn_a {
-                    Housenumber::CleanInt(v) => v.to_string(),
-                    Housenumber::Index(i) => self
-                        .housenumbers
-                        .get(*i as usize)
-                        .expect("Housenumber index greater then housenumber list length")
-                        .to_string(),
-                };
-                let b = match hn_b {
-                    Housenumber::CleanInt(v) => v.to_string(),
-                    Housenumber::Index(i) => self
-                        .housenumbers
-                        .get(*i as usize)
-                        .expect("Housenumber index greater then housenumber list length")
-                        .to_string(),
-                };
-                a.cmp(&b)
-            })
+            let streets = country
+                .cities
+                .iter_mut()
+                .flat_map(|c| c.districts.iter_mut())
+                .flat_map(|d| d.areas.iter_mut())
+                .flat_map(|a| a.streets.iter_mut());
+            let streets: Vec<&mut Street> = streets.collect();
+            let mut used: BTreeSet<u32> = BTreeSet::new();
+            for street in streets.iter() {
+                used.insert(street.index);
+                let translated = self.street_names.get(&street.index).into_iter().flatten();
+                used.extend(translated.map(|(_, name)| *name));
+            }
+            // The global pool is sorted, so are the names used in the country.
+            let used: Vec<u32> = used.into_iter().collect();
+            let local = |index: u32| first + used.binary_search(&index).unwrap() as u32;
+            for index in used.iter() {
+                if let Some(names) = self.street_names.get(index) {
+                    let names = names
+                        .iter()
+                        .map(|(lang, name)| (lang.clone(), local(*name)));
+                    street_names.insert(local(*index), names.collect());
+                }
+            }
+            for street in streets {
+                street.index = local(street.index);
+            }
+            pools.push((
+                first,
+                FrontCoded::new(used.iter().map(|i| &global[*i as usize])),
+            ));
+            first += used.len() as u32;
         }
+        self.streets = StreetPool::PerCountry(pools);
+        self.street_names = street_names;
+    }
+    pub fn sort(&mut self) {
+        self.countries.sort_by(|a, b| a.code.cmp(&b.code));
+        let pool = &self.housenumbers;
+        self.countries
+            .par_iter_mut()
+            .for_each(|country| country.sort_with(|a, b| compare_housenumbers(pool, a, b)));
     }
     pub fn count(&self) -> usize {
         self.countries.len()
     }
+    /// Write the world behind a header with its format version, see [World::read].
+    pub fn write(&self, mut output: impl Write) -> Result<(), String> {
+        output
+            .write_all(WORLD_MAGIC)
+            .and_then(|_| output.write_all(&WORLD_FORMAT_VERSION.to_le_bytes()))
+            .map_err(|e| e.to_string())?;
+        bincode::serialize_into(output, self).map_err(|e| e.to_string())
+    }
+    /// Read a world written by [World::write], rejecting worlds of another format version.
+    pub fn read(mut input: impl Read) -> Result<Self, String> {
+        let mut header = [0; 8];
+        input
+            .read_exact(&mut header)
+            .map_err(|e| format!("Could not read world header: {}", e))?;
+        if &header[..4] != WORLD_MAGIC {
+            return Err(
+                "Not a world file, or one written before format versions were introduced. \
+                Rebuild your world with `macs compress`."
+                    .to_string(),
+            );
+        }
+        let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if version != WORLD_FORMAT_VERSION {
+            return Err(format!(
+                "World file has format version {}, but version {} is expected. \
+                Rebuild your world with `macs compress`.",
+                version, WORLD_FORMAT_VERSION
+            ));
+        }
+        bincode::deserialize_from(input).map_err(|e| e.to_string())
+    }
+    /// Combine multiple worlds into one.
+    /// The string pools are unified first, then every street and house number
+    /// index of each world is translated into the new pools before its countries,
+    /// cities, postal areas and streets are merged into the result.
+    pub fn merge(worlds: Vec<World>) -> World {
+        let mut streets: HashSet<String> = HashSet::new();
+        let mut housenumbers: HashSet<String> = HashSet::new();
+        let mut units: HashSet<String> = HashSet::new();
+        for world in worlds.iter() {
+            streets.extend(world.streets.names().into_iter().map(Cow::into_owned));
+            housenumbers.extend(world.housenumbers.iter().cloned());
+            units.extend(world.units.iter().cloned());
+        }
+        let keep_osm_ids = worlds.iter().any(|w| w.keep_osm_ids);
+        let per_country = worlds
+            .iter()
+            .any(|w| matches!(w.streets, StreetPool::PerCountry(_)));
+        let mut abbreviations = Abbreviations::default();
+        for world in worlds.iter() {
+            abbreviations.merge(world.abbreviations.clone());
+        }
+        let mut merged = World::new(streets.into(), housenumbers.into())
+            .units(units.into())
+            .abbreviations(abbreviations)
+            .keep_osm_ids(keep_osm_ids);
+        for world in worlds {
+            let StreetPool::Global(pool) = &merged.streets else {
+                unreachable!("merged worlds are split into pools per country once all are merged");
+            };
+            let street_map: Vec<u32> = world
+                .streets
+                .names()
+                .iter()
+                .map(|s| {
+                    pool.index_of(&s.to_string())
+                        .expect("merged street pool did not contain street name")
+                        as u32
+                })
+                .collect();
+            let hn_map: Vec<u32> = world
+                .housenumbers
+                .iter()
+                .map(|hn| {
+                    merged
+                        .housenumbers
+                        .index_of(hn)
+                        .expect("merged house number pool did not contain house number")
+                        as u32
+                })
+                .collect();
+            let unit_map: Vec<u32> = world
+                .units
+                .iter()
+                .map(|unit| {
+                    merged
+                        .units
+                        .index_of(unit)
+                        .expect("merged unit pool did not contain unit") as u32
+                })
+                .collect();
+            for (street, names) in world.street_names {
+                let names_mut = merged
+                    .street_names
+                    .entry(street_map[street as usize])
+                    .or_default();
+                for (lang, name) in names {
+                    if !names_mut.iter().any(|(l, _)| l == &lang) {
+                        names_mut.push((lang, street_map[name as usize]));
+                    }
+                }
+            }
+            let pool = &merged.housenumbers;
+            for country in world.countries {
+                find_or_insert(
+                    &mut merged.countries,
+                    country.code.as_str(),
+                    |e| e.code.as_str(),
+                    || Country::new(country.code.clone()),
+                )
+                .merge(country, &street_map, &hn_map, &unit_map, |a, b| {
+                    compare_housenumbers(pool, a, b)
+                });
+            }
+        }
+        merged.sort();
+        if per_country {
+            merged.country_street_pools();
+        }
+        merged
+    }
     pub fn get_country(&self, country_code: String) -> Option<&Country> {
         self.countries
             .binary_search_by_key(&country_code.to_uppercase(), |c| c.code.to_uppercase())
@@ -330,111 +1256,382 @@ impl World {
     }
 }
 
+/// Names of the street and city in other languages.
+fn insert_names(world: &mut World, addr: &Address) {
+    for (lang, name) in addr.street_names.iter() {
+        world.insert_street_name(&addr.country, &addr.street, lang.clone(), name);
+    }
+    for (lang, name) in addr.city_names.iter() {
+        world.insert_city_name(&addr.country, &addr.city, lang.clone(), name.clone());
+    }
+}
+
+pub struct CompressOptions {
+    /// Keep the OSM id of every address in the world object.
+    pub keep_osm_ids: bool,
+    pub abbreviations: Abbreviations,
+    /// Values rejected by the cleaning rules, as json lines.
+    pub junk: Option<PathBuf>,
+    pub autofix: AutofixOptions,
+    /// Addresses the autofixer could not complete, with the reason, as json lines.
+    pub rejects: Option<PathBuf>,
+    /// Bytes to buffer before spilling sorted runs to temporary files, shared by all sorters.
+    pub memory_limit: Option<usize>,
+    /// See [World::country_street_pools].
+    pub country_street_pools: bool,
+}
+
+/// An address dropped as unfixable, for `--rejects`.
+#[derive(Serialize)]
+struct Reject<'a> {
+    #[serde(flatten)]
+    reason: &'a Unfixable,
+    address: &'a IncompleteAddress,
+}
+
+fn write_rejects(
+    writer: &mut impl Write,
+    unfixed: &[(IncompleteAddress, Unfixable)],
+) -> Result<(), String> {
+    for (address, reason) in unfixed {
+        serde_json::to_writer(&mut *writer, &Reject { reason, address })
+            .map_err(|e| e.to_string())?;
+        writer
+            .write_all("\n".as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Addresses buffered per country builder, before reading waits for it.
+const BUILDER_CHANNEL_CAPACITY: usize = 10_000;
+
+/// Sorters buffering at the same time, which share the memory limit.
+const SORTERS: usize = 5;
+
+/// Incomplete addresses fixed at once, extended to the end of the last street.
+const AUTOFIX_BATCH: usize = 100_000;
+
+/// Fix the incomplete addresses street by street, with the locations of complete addresses on those streets.
+/// Fixed addresses are pushed to `fixed_addresses`, to be inserted once the world is no longer read.
+fn autofix_sorted(
+    world: &World,
+    incomplete_addresses: ExternalSorter<IncompleteAddress>,
+    locations: ExternalSorter<Located>,
+    fixed_addresses: &mut ExternalSorter<(Address, Provenance)>,
+    options: &AutofixOptions,
+    rejects: Option<&Path>,
+) -> Result<(), String> {
+    let index = AutofixIndex::new(world);
+    let mut rejects = match rejects {
+        Some(path) => {
+            Some(BufWriter::new(File::create(path).map_err(|e| {
+                format!("Could not create rejects file {:?}: {}", path, e)
+            })?))
+        }
+        None => None,
+    };
+    let mut located = locations.finish()?.peekable();
+    let mut incomplete = incomplete_addresses.finish()?;
+    let mut batch: Vec<IncompleteAddress> = Vec::new();
+    let mut unfixed_count = 0;
+    let mut reasons: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut methods: BTreeMap<(Field, Method), usize> = BTreeMap::new();
+    loop {
+        let next = incomplete.next().transpose()?;
+        let same_street = match (&next, batch.last()) {
+            (Some(a), Some(last)) => a.street == last.street,
+            _ => false,
+        };
+        if batch.len() >= AUTOFIX_BATCH && !same_street || next.is_none() && !batch.is_empty() {
+            let streets: BTreeSet<&str> =
+                batch.iter().filter_map(|a| a.street.as_deref()).collect();
+            let mut nearby = Locations::default();
+            if let Some(last) = streets.last() {
+                while let Some(l) =
+                    located.next_if(|l| l.as_ref().map_or(true, |l| l.street.as_str() <= *last))
+                {
+                    let l = l?;
+                    if streets.contains(l.street.as_str()) {
+                        nearby.insert(l);
+                    }
+                }
+            }
+            let (fixed, unfixed) =
+                try_autofixing(&index, &nearby, options, std::mem::take(&mut batch));
+            info!(
+                "Fixed {} addresses, {} were unfixable.",
+                fixed.len(),
+                unfixed.len()
+            );
+            unfixed_count += unfixed.len();
+            for (_, reason) in unfixed.iter() {
+                *reasons.entry(reason.kind()).or_default() += 1;
+            }
+            if let Some(rejects) = &mut rejects {
+                write_rejects(rejects, &unfixed)?;
+            }
+            for fixed in fixed.into_iter() {
+                for inferred in fixed.inferred.iter() {
+                    *methods.entry(*inferred).or_default() += 1;
+                }
+                let provenance = fixed.provenance();
+                let mut addr = fixed.address;
+                // Postal codes and abbreviations depend on the country, which was unknown while reading.
+                // Postal codes not matching the country's format are kept as they were fixed with.
+                if fixed
+                    .inferred
+                    .iter()
+                    .any(|(field, _)| *field == Field::Country)
+                {
+                    if let Some(postcode) = postcode::normalize(&addr.country, &addr.postcode) {
+                        addr.postcode = postcode;
+                    }
+                    addr.street = world.expand_abbreviations(&addr.country, &addr.street, false);
+                    addr.city = world.expand_abbreviations(&addr.country, &addr.city, false);
+                }
+                fixed_addresses.push((addr, provenance))?;
+            }
+        }
+        match next {
+            Some(a) => batch.push(a),
+            None => break,
+        }
+    }
+    info!(
+        "Fixed {} addresses, {} were unfixable, skipped {} of duplicate OSM ids.",
+        fixed_addresses.count(),
+        unfixed_count,
+        incomplete.duplicates()
+    );
+    for (reason, count) in reasons {
+        info!("Unfixable because of {}: {}", reason, count);
+    }
+    for ((field, method), count) in methods {
+        info!("Inferred {:?} by {:?} {} times.", field, method, count);
+    }
+    if let Some(rejects) = &mut rejects {
+        rejects.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
 fn compress(
     streets: SortedVec<String>,
     hn: SortedVec<String>,
-    mut addresses: Vec<Address>,
-    incomplete_addresses: Vec<IncompleteAddress>,
-) {
-    let mut world = World::new(streets, hn);
-    let len = addresses.len();
-    let mut i = 0;
-    while let Some(addr) = addresses.pop() {
-        if i % 100_000 == 0 {
-            info!("Insert address {}/{} into world data structure", i, len);
-            addresses.shrink_to_fit();
+    units: SortedVec<String>,
+    addresses: ExternalSorter<Address>,
+    incomplete_addresses: ExternalSorter<IncompleteAddress>,
+    options: CompressOptions,
+    output: impl Write,
+) -> Result<(), String> {
+    let memory_limit = options.memory_limit.map(|limit| limit / SORTERS);
+    let mut world = World::new(streets, hn)
+        .units(units)
+        .abbreviations(options.abbreviations)
+        .keep_osm_ids(options.keep_osm_ids);
+    let mut locations = ExternalSorter::locations(memory_limit);
+    let mut street_names = ExternalSorter::street_names(memory_limit);
+    let len = addresses.count();
+    let mut sorted = addresses.finish()?;
+    // The addresses are sorted by country, so each country is sent to its own builder thread
+    // while the next one is being read. The channels are bounded, so a slow builder holds up
+    // reading instead of buffering its country, and at most as many builders as there are
+    // cores run at once.
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let builders = &world;
+    let countries = std::thread::scope(|s| -> Result<Vec<Country>, String> {
+        let mut countries = Vec::new();
+        let mut running: VecDeque<std::thread::ScopedJoinHandle<Country>> = VecDeque::new();
+        let mut current: Option<(String, SyncSender<Address>)> = None;
+        for (i, addr) in sorted.by_ref().enumerate() {
+            if i % 100_000 == 0 {
+                info!("Insert address {}/{} into world data structure", i, len);
+            }
+            let mut addr = addr?;
+            if let Some(located) = Located::of(&addr) {
+                locations.push(located)?;
+            }
+            if !addr.street_names.is_empty() {
+                let names = std::mem::take(&mut addr.street_names);
+                street_names.push((addr.country.clone(), addr.street.clone(), names))?;
+            }
+            if current.as_ref().map(|(code, _)| code) != Some(&addr.country) {
+                // Close the channel of the previous country before waiting for a builder.
+                drop(current.take());
+                if running.len() >= threads {
+                    let oldest = running.pop_front().unwrap();
+                    countries.push(oldest.join().unwrap());
+                }
+                info!("Building country {}...", addr.country);
+                let (sender, receiver) = sync_channel(BUILDER_CHANNEL_CAPACITY);
+                let code = addr.country.clone();
+                running
+                    .push_back(s.spawn(move || builders.build_country(code, receiver.into_iter())));
+                current = Some((addr.country.clone(), sender));
+            }
+            let (_, sender) = current.as_ref().unwrap();
+            sender
+                .send(addr)
+                .map_err(|_| "Country builder stopped unexpectedly".to_string())?;
         }
-        i += 1;
-        world.insert_address(
-            addr.country,
-            addr.city,
-            addr.postcode,
-            addr.street,
-            addr.housenumber,
-        );
-    }
-    info!("Sorting every wolrd entry...");
-    world.sort();
-
+        drop(current);
+        for builder in running {
+            countries.push(builder.join().unwrap());
+        }
+        Ok(countries)
+    })?;
     info!(
-        "Trying to autofix {} addresses.",
-        incomplete_addresses.len()
+        "Skipped {} complete addresses of duplicate OSM ids.",
+        sorted.duplicates()
     );
-    let (fixed, unfixed) = try_autofixing(&world, incomplete_addresses);
+    drop(sorted);
+    for country in countries {
+        world.insert_country(country)?;
+    }
+    for item in street_names.finish()? {
+        let (country, street, names) = item?;
+        for (lang, name) in names {
+            world.insert_street_name(&country, &street, lang, &name);
+        }
+    }
+    // Every level of the world is kept sorted while inserting, so it can be searched right away.
     info!(
-        "Fixed {} addresses, {} were unfixable.",
-        fixed.len(),
-        unfixed.len()
+        "Trying to autofix {} addresses.",
+        incomplete_addresses.count()
     );
-    for addr in fixed.into_iter() {
+    let mut fixed_addresses = ExternalSorter::fixed_addresses(memory_limit);
+    autofix_sorted(
+        &world,
+        incomplete_addresses,
+        locations,
+        &mut fixed_addresses,
+        &options.autofix,
+        options.rejects.as_deref(),
+    )?;
+    for item in fixed_addresses.finish()? {
+        let (addr, provenance) = item?;
+        insert_names(&mut world, &addr);
         world.insert_address(
             addr.country,
             addr.city,
+            addr.district,
             addr.postcode,
             addr.street,
             addr.housenumber,
+            addr.id,
+            addr.unit,
+            provenance,
         )
     }
     info!("Sort again...");
     world.sort();
+    if options.country_street_pools {
+        info!("Splitting street names into pools per country...");
+        world.country_street_pools();
+    }
 
     info!(
-        "Done. Dumping world containing {} countries to stdout...",
+        "Done. Dumping world containing {} countries...",
         world.count()
     );
-    let stdout = io::stdout().lock();
-    bincode::serialize_into(stdout, &world).unwrap();
+    world.write(output)?;
     info!("Done!");
+    Ok(())
 }
 
-pub fn read_and_compress() -> Result<(), String> {
+pub fn read_and_compress(options: CompressOptions) -> Result<(), String> {
     info!("Reading jsonl from stdin...");
-    let stdin = io::stdin().lock();
-    let mut addresses: Vec<Address> = Vec::new();
-    let mut incomplete_addresses: Vec<IncompleteAddress> = Vec::new();
-    let mut streets: HashSet<String> = HashSet::new();
-    let mut uncompressable_house_numbers: HashSet<String> = HashSet::new();
-    for (i, item) in iter_items(stdin).enumerate() {
+    compress_from(io::stdin().lock(), io::stdout().lock(), options)
+}
+
+/// Unique strings of a sorter, for a pool of the world.
+fn pool(strings: ExternalSorter<String>) -> Result<SortedVec<String>, String> {
+    let strings: Vec<String> = strings.finish()?.collect::<Result<_, String>>()?;
+    Ok(strings.into())
+}
+
+/// Read json lines from `input` and write the world object to `output`.
+/// The same input always results in the same bytes, whatever the memory limit.
+fn compress_from(
+    input: impl Read,
+    output: impl Write,
+    options: CompressOptions,
+) -> Result<(), String> {
+    let memory_limit = options.memory_limit.map(|limit| limit / SORTERS);
+    // Addresses of the same OSM id, e.g. in overlapping extracts, are dropped while sorting.
+    let mut addresses = ExternalSorter::addresses(memory_limit);
+    let mut incomplete_addresses = ExternalSorter::incomplete_addresses(memory_limit);
+    let mut streets = ExternalSorter::strings(memory_limit);
+    let mut uncompressable_house_numbers = ExternalSorter::strings(memory_limit);
+    let mut units = ExternalSorter::strings(memory_limit);
+    let mut invalid_postcodes: BTreeMap<String, usize> = BTreeMap::new();
+    let mut cleaner = match &options.junk {
+        Some(path) => Cleaner::default().junk_file(path)?,
+        None => Cleaner::default(),
+    };
+    for (i, item) in iter_items(input).enumerate() {
         if i % 100_000 == 0 {
-            info!(
-                "Processed {} addresses, {} unique street names, {} unique uncompressable house numbers",
-                  i, streets.len(), uncompressable_house_numbers.len());
-        }
-        let item = item?;
-        if let Some(street) = &item.street {
-            streets.insert(street.clone());
+            info!("Processed {} addresses", i);
         }
-        if let Some(hn) = &item.housenumber {
-            if !num_compressable(hn.as_str()) {
-                uncompressable_house_numbers.insert(hn.clone());
+        for item in cleaner.clean(item?)? {
+            let item = normalize_address(item, &mut invalid_postcodes);
+            let item = expand_abbreviations(item, &options.abbreviations);
+            if let Some(street) = &item.street {
+                streets.push(street.clone())?;
+                // Expanded once the country is autofixed, see `compress`.
+                if item.country.is_none() {
+                    for expanded in options.abbreviations.expand_in_any(street) {
+                        streets.push(expanded)?;
+                    }
+                }
+            }
+            for name in item.street_names.values() {
+                streets.push(name.clone())?;
+            }
+            if let Some(hn) = &item.housenumber {
+                if !num_compressable(hn.as_str()) {
+                    uncompressable_house_numbers.push(hn.clone())?;
+                }
+            }
+            if let Some(unit) = &item.unit {
+                units.push(unit.clone())?;
+            }
+            if item.is_complete() {
+                addresses.push(item.into_complete().unwrap())?;
+            } else {
+                incomplete_addresses.push(item)?;
             }
-        }
-        if item.is_complete() {
-            addresses.push(item.into_complete().unwrap());
-        } else {
-            incomplete_addresses.push(item);
         }
     }
+    cleaner.finish()?;
 
     info!(
         "Collected {} complete addresses, {} potentially fixable addresses.",
-        addresses.len(),
-        incomplete_addresses.len()
-    );
-    info!("Sort streets ({})...", streets.len());
-    let streets_sorted: SortedVec<String> = streets.into_iter().collect::<Vec<_>>().into();
-    info!(
-        "Sort house numbers ({})...",
-        uncompressable_house_numbers.len()
+        addresses.count(),
+        incomplete_addresses.count()
     );
-    let housenumbers_sorted: SortedVec<String> = uncompressable_house_numbers
-        .into_iter()
-        .collect::<Vec<String>>()
-        .into();
+    if !invalid_postcodes.is_empty() {
+        info!(
+            "Dropped {} invalid postal codes, left to the autofixer: {}",
+            invalid_postcodes.values().sum::<usize>(),
+            invalid_postcodes
+                .iter()
+                .map(|(country, n)| format!("{} {}", country, n))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    info!("Sort streets...");
+    let streets_sorted = pool(streets)?;
+    info!("Sort house numbers...");
+    let housenumbers_sorted = pool(uncompressable_house_numbers)?;
+    info!("Sort units...");
+    let units_sorted = pool(units)?;
 
     info!(
         "Processed {} addresses, {} unique street names, {} unique uncompressable house numbers",
-        addresses.len(),
+        addresses.count(),
         streets_sorted.len(),
         housenumbers_sorted.len()
     );
@@ -442,15 +1639,21 @@ pub fn read_and_compress() -> Result<(), String> {
     compress(
         streets_sorted,
         housenumbers_sorted,
+        units_sorted,
         addresses,
         incomplete_addresses,
-    );
-
-    Ok(())
+        options,
+        output,
+    )
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
     use super::*;
     #[test]
     fn auto_correct_cc() {
@@ -466,4 +1669,715 @@ mod test {
         assert_eq!(autocorrect_country_code("GB".to_string()), "GB".to_string());
         assert_eq!(autocorrect_country_code("CA".to_string()), "CA".to_string());
     }
+
+    pub fn world_of(addresses: &[(&str, &str, &str, &str, &str)]) -> World {
+        let streets: HashSet<String> = addresses.iter().map(|a| a.3.to_string()).collect();
+        let housenumbers: HashSet<String> = addresses
+            .iter()
+            .map(|a| a.4.to_string())
+            .filter(|hn| !num_compressable(hn))
+            .collect();
+        let mut world = World::new(streets.into(), housenumbers.into());
+        for (co, ci, zip, st, hn) in addresses {
+            world.insert_address(
+                co.to_string(),
+                ci.to_string(),
+                None,
+                zip.to_string(),
+                st.to_string(),
+                hn.to_string(),
+                None,
+                None,
+                Provenance::ORIGINAL,
+            );
+        }
+        world.sort();
+        world
+    }
+
+    #[test]
+    fn merge_worlds() {
+        let a = world_of(&[
+            ("DE", "Berlin", "10115", "Invalidenstraße", "1"),
+            ("DE", "Berlin", "10115", "Invalidenstraße", "2a"),
+            ("NL", "Utrecht", "3511 AA", "Oudegracht", "7"),
+        ]);
+        let b = world_of(&[
+            ("DE", "Berlin", "10115", "Chausseestraße", "12b"),
+            ("DE", "Berlin", "10115", "Invalidenstraße", "2a"),
+            ("DE", "Berlin", "10115", "Invalidenstraße", "3"),
+            ("FR", "Lyon", "69001", "Rue de la République", "4"),
+        ]);
+        let merged = World::merge(vec![a, b]);
+        assert_eq!(merged.count(), 3);
+        let area = merged
+            .get_country("DE".into())
+            .and_then(|c| c.get_city("Berlin"))
+            .and_then(|c| c.get_postal_areas("10115", None).pop())
+            .unwrap();
+        let streets: Vec<Cow<str>> = area
+            .iter_streets_prefixed("".into(), None, &merged)
+            .collect();
+        assert_eq!(streets, vec!["Chausseestraße", "Invalidenstraße"]);
+        let housenumbers: Vec<String> = area
+            .get_street("Invalidenstraße", &merged)
+            .unwrap()
+            .housenumber_iter(&merged)
+            .collect();
+        assert_eq!(housenumbers, vec!["1", "2a", "3"]);
+        let housenumbers: Vec<String> = area
+            .get_street("Chausseestraße", &merged)
+            .unwrap()
+            .housenumber_iter(&merged)
+            .collect();
+        assert_eq!(housenumbers, vec!["12b"]);
+    }
+
+    #[test]
+    fn osm_ids_follow_sorting() {
+        use osmpbfreader::{NodeId, WayId};
+        let streets: HashSet<String> = ["Oudegracht".to_string()].into();
+        let mut world = World::new(streets.into(), HashSet::new().into()).keep_osm_ids(true);
+        for (hn, id) in [
+            ("9", Some(OsmId::Way(WayId(9)))),
+            ("7", None),
+            ("8", Some(OsmId::Node(NodeId(8)))),
+        ] {
+            world.insert_address(
+                "NL".into(),
+                "Utrecht".into(),
+                None,
+                "3511 AA".into(),
+                "Oudegracht".into(),
+                hn.into(),
+                id,
+                None,
+                Provenance::ORIGINAL,
+            );
+        }
+        world.sort();
+        let street = world
+            .get_country("NL".into())
+            .and_then(|c| c.get_city("Utrecht"))
+            .and_then(|c| c.get_postal_areas("3511 AA", None).pop())
+            .and_then(|a| a.get_street("Oudegracht", &world))
+            .unwrap();
+        let housenumbers: Vec<(String, Option<OsmId>)> = street
+            .iter_housenumber_details_prefixed("".into(), &world)
+            .map(|(hn, osm_id, _)| (hn, osm_id))
+            .collect();
+        assert_eq!(
+            housenumbers,
+            vec![
+                ("7".to_string(), None),
+                ("8".to_string(), Some(OsmId::Node(NodeId(8)))),
+                ("9".to_string(), Some(OsmId::Way(WayId(9)))),
+            ]
+        );
+    }
+
+    #[test]
+    fn units_below_housenumbers() {
+        let streets: HashSet<String> = ["Oudegracht".to_string()].into();
+        let units =
+            |units: &[&str]| -> HashSet<String> { units.iter().map(|u| u.to_string()).collect() };
+        let mut worlds = vec![
+            World::new(streets.clone().into(), HashSet::new().into())
+                .units(units(&["B", "A"]).into()),
+            World::new(streets.into(), HashSet::new().into()).units(units(&["A", "C"]).into()),
+        ];
+        for (i, hn, unit) in [
+            (0, "7", Some("B")),
+            (0, "7", Some("A")),
+            (0, "9", None),
+            (1, "7", Some("C")),
+            (1, "7", Some("A")),
+            (1, "8", Some("A")),
+        ] {
+            worlds[i].insert_address(
+                "NL".into(),
+                "Utrecht".into(),
+                None,
+                "3511 AA".into(),
+                "Oudegracht".into(),
+                hn.into(),
+                None,
+                unit.map(String::from),
+                Provenance::ORIGINAL,
+            );
+        }
+        let merged = World::merge(worlds);
+        let street = merged
+            .get_country("NL".into())
+            .and_then(|c| c.get_city("Utrecht"))
+            .and_then(|c| c.get_postal_areas("3511 AA", None).pop())
+            .and_then(|a| a.get_street("Oudegracht", &merged))
+            .unwrap();
+        let units_of = |hn: &str| -> Option<Vec<&str>> {
+            let units = street.iter_units_prefixed(hn, "".into(), &merged)?;
+            Some(units.map(|u| u.as_str()).collect())
+        };
+        assert_eq!(units_of("7"), Some(vec!["A", "B", "C"]));
+        assert_eq!(units_of("8"), Some(vec!["A"]));
+        assert_eq!(units_of("9"), Some(vec![]));
+        assert_eq!(units_of("10"), None);
+    }
+
+    #[test]
+    fn names_in_other_languages() {
+        let streets: HashSet<String> = ["Rue Neuve - Nieuwstraat", "Rue Neuve", "Nieuwstraat"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut world = World::new(streets.into(), HashSet::new().into());
+        let (city, street) = ("Bruxelles - Brussel", "Rue Neuve - Nieuwstraat");
+        world.insert_city_name("BE", city, "fr".into(), "Bruxelles".into());
+        world.insert_city_name("BE", city, "nl".into(), "Brussel".into());
+        world.insert_street_name("BE", street, "fr".into(), "Rue Neuve");
+        world.insert_street_name("BE", street, "nl".into(), "Nieuwstraat");
+        world.insert_address(
+            "BE".into(),
+            city.into(),
+            None,
+            "1000".into(),
+            street.into(),
+            "1".into(),
+            None,
+            None,
+            Provenance::ORIGINAL,
+        );
+        world.sort();
+        let country = world.get_country("BE".into()).unwrap();
+        let cities: Vec<&String> = country
+            .iter_cities_prefixed("brus".into(), Some("fr"))
+            .collect();
+        assert_eq!(cities, vec!["Bruxelles"]);
+        let area = country
+            .get_city("Brussel")
+            .and_then(|c| c.get_postal_areas("1000", None).pop())
+            .unwrap();
+        let streets: Vec<Cow<str>> = area
+            .iter_streets_prefixed("nieuw".into(), Some("nl"), &world)
+            .collect();
+        assert_eq!(streets, vec!["Nieuwstraat"]);
+        let streets: Vec<Cow<str>> = area
+            .iter_streets_prefixed("rue".into(), Some("de"), &world)
+            .collect();
+        assert_eq!(streets, vec!["Rue Neuve - Nieuwstraat"]);
+        assert!(area.get_street("rue neuve", &world).is_some());
+    }
+
+    #[test]
+    fn streets_across_districts() {
+        let streets: HashSet<String> = ["Schönhauser Allee".to_string()].into();
+        let mut world = World::new(streets.into(), HashSet::new().into());
+        for (district, hn) in [
+            (Some("Prenzlauer Berg"), "100"),
+            (Some("Mitte"), "1"),
+            (None, "200"),
+        ] {
+            world.insert_address(
+                "DE".into(),
+                "Berlin".into(),
+                district.map(String::from),
+                "10119".into(),
+                "Schönhauser Allee".into(),
+                hn.into(),
+                None,
+                None,
+                Provenance::ORIGINAL,
+            );
+        }
+        world.sort();
+        let city = world
+            .get_country("DE".into())
+            .and_then(|c| c.get_city("Berlin"))
+            .unwrap();
+        let districts: Vec<&String> = city.iter_districts_prefixed("".into()).collect();
+        assert_eq!(districts, vec!["Mitte", "Prenzlauer Berg"]);
+        assert_eq!(city.iter_zips_prefixed("".into(), None).count(), 1);
+        assert_eq!(city.iter_zips_prefixed("".into(), Some("mitte")).count(), 1);
+        assert_eq!(
+            city.iter_zips_prefixed("".into(), Some("Pankow")).count(),
+            0
+        );
+        assert_eq!(city.get_postal_areas("10119", None).len(), 3);
+        let areas = city.get_postal_areas("10119", Some("prenzlauer berg"));
+        let housenumbers: Vec<String> = areas[0]
+            .get_street("Schönhauser Allee", &world)
+            .unwrap()
+            .housenumber_iter(&world)
+            .collect();
+        assert_eq!(housenumbers, vec!["100"]);
+        assert!(city.get_postal_areas("10119", Some("Pankow")).is_empty());
+    }
+
+    #[test]
+    fn provenance_follows_sorting() {
+        let streets: HashSet<String> = ["Kirchweg".to_string()].into();
+        let mut world = World::new(streets.into(), HashSet::new().into());
+        let inferred =
+            Provenance::inferred(Provenance::INFERRED_ZIP | Provenance::INTERPOLATED, 0.8);
+        for (hn, provenance) in [
+            ("9", Provenance::ORIGINAL),
+            ("3", inferred),
+            ("5", inferred),
+            ("5", Provenance::ORIGINAL),
+            ("1", Provenance::ORIGINAL),
+        ] {
+            world.insert_address(
+                "DE".into(),
+                "Hamburg".into(),
+                None,
+                "20095".into(),
+                "Kirchweg".into(),
+                hn.into(),
+                None,
+                None,
+                provenance,
+            );
+        }
+        world.sort();
+        let merged = World::merge(vec![world]);
+        let street = merged
+            .get_country("DE".into())
+            .and_then(|c| c.get_city("Hamburg"))
+            .and_then(|c| c.get_postal_areas("20095", None).pop())
+            .and_then(|a| a.get_street("Kirchweg", &merged))
+            .unwrap();
+        let details: Vec<(String, Vec<&str>)> = street
+            .iter_housenumber_details_prefixed("".into(), &merged)
+            .map(|(hn, _, provenance)| (hn, provenance.flags()))
+            .collect();
+        assert_eq!(
+            details,
+            vec![
+                ("1".to_string(), vec!["original"]),
+                ("3".to_string(), vec!["inferred-zip", "interpolated"]),
+                ("5".to_string(), vec!["original"]),
+                ("9".to_string(), vec!["original"]),
+            ]
+        );
+        assert_eq!(inferred.confidence(), 0.8);
+    }
+
+    #[test]
+    fn countries_built_apart() {
+        let addresses = [
+            ("DE", "Berlin", "10115", "Invalidenstraße", "2a"),
+            ("DE", "Berlin", "10115", "Invalidenstraße", "1"),
+            ("DE", "Potsdam", "14467", "Am Kanal", "5"),
+            ("NL", "Utrecht", "3511 AA", "Oudegracht", "7"),
+        ];
+        let inserted = world_of(&addresses);
+        let streets: HashSet<String> = addresses.iter().map(|a| a.3.to_string()).collect();
+        let mut built = World::new(streets.into(), HashSet::from(["2a".to_string()]).into());
+        for code in ["NL", "DE"] {
+            let country_addresses =
+                addresses
+                    .iter()
+                    .filter(|a| a.0 == code)
+                    .map(|(co, ci, zip, st, hn)| Address {
+                        country: co.to_string(),
+                        city: ci.to_string(),
+                        postcode: zip.to_string(),
+                        street: st.to_string(),
+                        housenumber: hn.to_string(),
+                        ..Default::default()
+                    });
+            let country = built.build_country(code.to_string(), country_addresses);
+            built.insert_country(country).unwrap();
+        }
+        assert_eq!(
+            bincode::serialize(&built).unwrap(),
+            bincode::serialize(&inserted).unwrap()
+        );
+        assert!(built.insert_country(Country::new("DE".into())).is_err());
+    }
+
+    fn compress_options(memory_limit: Option<usize>) -> CompressOptions {
+        CompressOptions {
+            keep_osm_ids: true,
+            abbreviations: Abbreviations::defaults(),
+            junk: None,
+            autofix: AutofixOptions {
+                min_confidence: 0.5,
+                ..Default::default()
+            },
+            rejects: None,
+            memory_limit,
+            country_street_pools: false,
+        }
+    }
+
+    #[test]
+    fn reproducible_builds() {
+        let compress_fixture = |memory_limit| {
+            let input = include_str!("../tests/fixtures/addresses.jsonl");
+            let mut world = Vec::new();
+            compress_from(input.as_bytes(), &mut world, compress_options(memory_limit)).unwrap();
+            world
+        };
+        let checksum = |world: &[u8]| {
+            let mut hasher = DefaultHasher::new();
+            world.hash(&mut hasher);
+            hasher.finish()
+        };
+        let first = compress_fixture(None);
+        for memory_limit in [None, Some(1), Some(2048)] {
+            assert_eq!(checksum(&compress_fixture(memory_limit)), checksum(&first));
+        }
+        let world = World::read(first.as_slice()).unwrap();
+        assert_eq!(world.count(), 4);
+        // Equally near to both postal areas of the street, the first located address wins.
+        let area = world
+            .get_country("DE".into())
+            .and_then(|c| c.get_city("Neustadt"))
+            .and_then(|c| c.get_postal_areas("67433", None).pop())
+            .unwrap();
+        let housenumbers: Vec<String> = area
+            .get_street("Hauptstraße", &world)
+            .unwrap()
+            .housenumber_iter(&world)
+            .collect();
+        assert_eq!(housenumbers, vec!["1", "2", "5"]);
+        // Of otherwise equal addresses, the first one's names are kept.
+        let city = world
+            .get_country("BE".into())
+            .and_then(|c| c.get_city("Brussel"))
+            .unwrap();
+        assert_eq!(city.name_in(Some("de")), "Brüssel");
+    }
+
+    #[test]
+    fn rejects_as_json_lines() {
+        let input = concat!(
+            r#"{"id":"n1","country":"DE","city":"Neustadt","postcode":"67433","street":"Hauptstraße","housenumber":"1"}"#,
+            "\n",
+            r#"{"id":"n2","country":"AT","city":"Neustadt","postcode":"4911","street":"Hauptstraße","housenumber":"1"}"#,
+            "\n",
+            r#"{"id":"n3","city":"Neustadt","street":"Hauptstraße","housenumber":"2"}"#,
+            "\n",
+            r#"{"id":"n4","country":"DE","street":"Hauptstraße","housenumber":"3"}"#,
+            "\n",
+        );
+        let rejects = tempfile::NamedTempFile::new().unwrap();
+        let options = CompressOptions {
+            rejects: Some(rejects.path().to_path_buf()),
+            ..compress_options(None)
+        };
+        compress_from(input.as_bytes(), io::sink(), options).unwrap();
+        let written = std::fs::read_to_string(rejects.path()).unwrap();
+        assert_eq!(
+            written.lines().collect::<Vec<&str>>(),
+            vec![
+                r#"{"reason":"ambiguous_city","countries":2,"address":{"id":"n3","country":null,"city":"Neustadt","postcode":null,"street":"Hauptstraße","housenumber":"2"}}"#,
+                r#"{"reason":"missing_location","address":{"id":"n4","country":"DE","city":null,"postcode":null,"street":"Hauptstraße","housenumber":"3"}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn world_format_versions() {
+        let world = world_of(&[("DE", "Berlin", "10115", "Invalidenstraße", "1")]);
+        let mut written = Vec::new();
+        world.write(&mut written).unwrap();
+        assert_eq!(World::read(written.as_slice()).unwrap().count(), 1);
+        written[4] = 0;
+        let error = World::read(written.as_slice()).err().unwrap();
+        assert!(error.starts_with("World file has format version 0, but version 1 is expected."));
+        let unversioned = bincode::serialize(&world).unwrap();
+        let error = World::read(unversioned.as_slice()).err().unwrap();
+        assert!(error.ends_with("Rebuild your world with `macs compress`."));
+    }
+
+    #[test]
+    fn autofixed_addresses_are_normalized() {
+        let input = concat!(
+            r#"{"id":"n1","country":"DE","city":"Neustadt","postcode":"67433","street":"Hauptstraße","housenumber":"1","long":81400000,"lat":493500000}"#,
+            "\n",
+            r#"{"id":"n2","city":"Neustadt","postcode":"67433","street":"Hauptstr.","housenumber":"2"}"#,
+            "\n",
+            r#"{"id":"n3","city":"Neustadt","postcode":"D-67433","street":"Hauptstraße","housenumber":"3"}"#,
+            "\n",
+            // Not expanded anywhere else in the input.
+            r#"{"id":"n4","city":"Neustadt","postcode":"67433","street":"Kirchstr.","housenumber":"4"}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        compress_from(input.as_bytes(), &mut output, compress_options(None)).unwrap();
+        let world = World::read(output.as_slice()).unwrap();
+        let area = world
+            .get_country("DE".into())
+            .and_then(|c| c.get_city("Neustadt"))
+            .and_then(|c| c.get_postal_areas("67433", None).pop())
+            .unwrap();
+        let housenumbers = |street: &str| -> Vec<String> {
+            area.get_street(street, &world)
+                .unwrap()
+                .housenumber_iter(&world)
+                .collect()
+        };
+        assert_eq!(housenumbers("Hauptstraße"), vec!["1", "2", "3"]);
+        assert_eq!(housenumbers("Kirchstraße"), vec!["4"]);
+    }
+
+    #[test]
+    fn lowercase_country_codes() {
+        let input = concat!(
+            r#"{"id":"n1","country":"de","city":"Berlin","postcode":"D-10115","street":"Invalidenstr.","housenumber":"1"}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+        compress_from(input.as_bytes(), &mut output, compress_options(None)).unwrap();
+        let world = World::read(output.as_slice()).unwrap();
+        let area = world
+            .get_country("DE".into())
+            .and_then(|c| c.get_city("Berlin"))
+            .and_then(|c| c.get_postal_areas("10115", None).pop())
+            .unwrap();
+        assert!(area.get_street("Invalidenstraße", &world).is_some());
+    }
+
+    /// Synthetic addresses in the order of the external sort, or reversed.
+    /// 10 countries, 1000 cities each, 5 postal codes per city, 20 streets per postal code.
+    fn synthetic_addresses(
+        n: usize,
+        reversed: bool,
+    ) -> impl Iterator<Item = (String, String, String, String, String)> {
+        let order = move |i: usize, len: usize| if reversed { len - 1 - i } else { i };
+        (0..n).map(move |i| {
+            let (hn, rest) = (i % 10, i / 10);
+            let (street, rest) = (rest % 20, rest / 20);
+            let (zip, rest) = (rest % 5, rest / 5);
+            let (city, country) = (rest % 1000, rest / 1000 % 10);
+            let housenumber = match order(hn, 10) {
+                7 => "7a".to_string(),
+                hn => (hn + 1).to_string(),
+            };
+            (
+                format!("C{}", order(country, 10)),
+                format!("City {:04}", order(city, 1000)),
+                format!("{:05}", order(city, 1000) * 5 + order(zip, 5)),
+                format!("Street {:02}", order(street, 20)),
+                housenumber,
+            )
+        })
+    }
+
+    /// A level of the baseline of `bench_insert`, searched linearly on every insert and sorted once.
+    #[derive(Default)]
+    struct Linear<T>(Vec<(String, T)>);
+
+    impl<T: Default> Linear<T> {
+        fn get(&mut self, key: String) -> &mut T {
+            let i = match self.0.iter().position(|(k, _)| *k == key) {
+                Some(i) => i,
+                None => {
+                    self.0.push((key, T::default()));
+                    self.0.len() - 1
+                }
+            };
+            &mut self.0[i].1
+        }
+
+        fn sort(&mut self, mut inner: impl FnMut(&mut T)) {
+            self.0.sort_by(|a, b| a.0.cmp(&b.0));
+            for (_, child) in self.0.iter_mut() {
+                inner(child);
+            }
+        }
+    }
+
+    /// Run with `cargo test --release bench_insert -- --ignored --nocapture`.
+    /// The number of addresses can be set with `MACS_BENCH_ADDRESSES`.
+    /// Compares keeping the levels sorted to searching every level linearly with `iter().position`
+    /// and `contains`, and sorting once at the end.
+    #[test]
+    #[ignore]
+    fn bench_insert() {
+        let n: usize = std::env::var("MACS_BENCH_ADDRESSES")
+            .map(|n| n.parse().unwrap())
+            .unwrap_or(10_000_000);
+        let streets: HashSet<String> = (0..20).map(|s| format!("Street {:02}", s)).collect();
+        let housenumbers: HashSet<String> = ["7a".to_string()].into();
+        for reversed in [false, true] {
+            let order = if reversed { " in reverse order" } else { "" };
+            let mut world = World::new(streets.clone().into(), housenumbers.clone().into());
+            let start = std::time::Instant::now();
+            for (co, ci, zip, st, hn) in synthetic_addresses(n, reversed) {
+                world.insert_address(co, ci, None, zip, st, hn, None, None, Provenance::ORIGINAL);
+            }
+            let inserted = start.elapsed();
+            world.sort();
+            println!(
+                "{} addresses{}: inserted in {:.2?}, sorted in {:.2?}",
+                n,
+                order,
+                inserted,
+                start.elapsed() - inserted
+            );
+            assert_eq!(world.count(), ((n - 1) / 1_000_000 + 1).min(10));
+
+            let mut linear: Linear<Linear<Linear<Linear<Vec<String>>>>> = Linear::default();
+            let start = std::time::Instant::now();
+            for (co, ci, zip, st, hn) in synthetic_addresses(n, reversed) {
+                let housenumbers = linear.get(co).get(ci).get(zip).get(st);
+                if !housenumbers.contains(&hn) {
+                    housenumbers.push(hn);
+                }
+            }
+            let inserted = start.elapsed();
+            linear.sort(|cities| {
+                cities.sort(|areas| areas.sort(|streets| streets.sort(|hns| hns.sort())))
+            });
+            println!(
+                "{} addresses{} with linear search: inserted in {:.2?}, sorted in {:.2?}",
+                n,
+                order,
+                inserted,
+                start.elapsed() - inserted
+            );
+            assert_eq!(linear.0.len(), world.count());
+        }
+    }
+
+    #[test]
+    fn street_pools_per_country() {
+        let world = || {
+            let mut world = world_of(&[
+                ("BE", "Brussel", "1000", "Nieuwstraat", "1"),
+                ("BE", "Brussel", "1000", "Rue Neuve", "1"),
+                ("DE", "Berlin", "10115", "Invalidenstraße", "1"),
+                ("DE", "Berlin", "10115", "Hauptstraße", "1"),
+                ("DE", "Berlin", "10117", "Hauptstraße", "2"),
+                ("NL", "Utrecht", "3511 AA", "Nieuwstraat", "7"),
+            ]);
+            world.insert_street_name("NL", "Nieuwstraat", "fr".into(), "Rue Neuve");
+            world.country_street_pools();
+            world
+        };
+        let split = world();
+        let merged = World::merge(vec![world()]);
+        for world in [&split, &merged] {
+            // Translations are part of the pool of every country using the street.
+            let names: Vec<Cow<str>> = world.streets.names();
+            assert_eq!(
+                names,
+                vec![
+                    "Nieuwstraat",
+                    "Rue Neuve",
+                    "Hauptstraße",
+                    "Invalidenstraße",
+                    "Nieuwstraat",
+                    "Rue Neuve"
+                ]
+            );
+            assert_eq!(world.street_index("DE", "Invalidenstraße"), Some(3));
+            // Looked up in the pool of the country, not the first one containing the name.
+            assert_eq!(world.street_index("BE", "Rue Neuve"), Some(1));
+            assert_eq!(world.street_index("NL", "Rue Neuve"), Some(5));
+            assert_eq!(world.street_index("DE", "Rue Neuve"), None);
+            assert_eq!(world.street_index("FR", "Rue Neuve"), None);
+            let area = |country: &str, city: &str, zip: &str| {
+                world
+                    .get_country(country.into())
+                    .and_then(|c| c.get_city(city))
+                    .and_then(|c| c.get_postal_areas(zip, None).pop())
+                    .unwrap()
+            };
+            let streets: Vec<Cow<str>> = area("NL", "Utrecht", "3511 AA")
+                .iter_streets_prefixed("rue".into(), Some("fr"), world)
+                .collect();
+            assert_eq!(streets, vec!["Rue Neuve"]);
+            assert!(area("DE", "Berlin", "10117")
+                .get_street("hauptstraße", world)
+                .is_some());
+        }
+    }
+
+    /// A made up street name, the same for the same street of a postal area.
+    fn synthetic_street_name(country: &str, zip: &str, street: &str) -> String {
+        const SYLLABLES: [&str; 16] = [
+            "ber", "lin", "ha", "mel", "dorf", "kir", "chen", "wal", "de", "ro", "sen", "bach",
+            "tal", "hei", "mar", "ko",
+        ];
+        const KINDS: [&str; 4] = ["straße", "weg", "allee", "platz"];
+        let mut hasher = DefaultHasher::new();
+        (country, zip, street).hash(&mut hasher);
+        let h = hasher.finish() as usize;
+        let name: String = (0..5).map(|i| SYLLABLES[(h >> (4 * i)) & 15]).collect();
+        let mut chars = name.chars();
+        let first = chars.next().unwrap().to_uppercase();
+        format!("{}{}{}", first, chars.as_str(), KINDS[(h >> 20) & 3])
+    }
+
+    /// Memory and latency of the global street pool and the front coded pools per country.
+    /// Run with `cargo test --release bench_street_pools -- --ignored --nocapture`.
+    /// The number of addresses can be set with `MACS_BENCH_ADDRESSES`.
+    #[test]
+    #[ignore]
+    fn bench_street_pools() {
+        let n: usize = std::env::var("MACS_BENCH_ADDRESSES")
+            .map(|n| n.parse().unwrap())
+            .unwrap_or(10_000_000);
+        let streets: HashSet<String> = synthetic_addresses(n, false)
+            .map(|(co, _, zip, st, _)| synthetic_street_name(&co, &zip, &st))
+            .collect();
+        let mut world = World::new(streets.into(), HashSet::from(["7a".to_string()]).into());
+        for (co, ci, zip, st, hn) in synthetic_addresses(n, false) {
+            let st = synthetic_street_name(&co, &zip, &st);
+            world.insert_address(co, ci, None, zip, st, hn, None, None, Provenance::ORIGINAL);
+        }
+        world.sort();
+        let mut names: Vec<String> = Vec::new();
+        for layout in ["global pool", "pools per country"] {
+            if layout == "pools per country" {
+                world.country_street_pools();
+            }
+            let pool_size = bincode::serialized_size(&world.streets).unwrap();
+            let world_size = bincode::serialized_size(&world).unwrap();
+            let areas: Vec<&PostalArea> = world
+                .iter_countries()
+                .flat_map(|c| c.iter_cities())
+                .flat_map(|c| c.iter_zips())
+                .collect();
+            let start = std::time::Instant::now();
+            let listed: Vec<String> = areas
+                .iter()
+                .flat_map(|area| area.iter_streets_prefixed("".into(), None, &world))
+                .map(Cow::into_owned)
+                .collect();
+            let listing = start.elapsed();
+            let start = std::time::Instant::now();
+            let prefixed = areas
+                .iter()
+                .flat_map(|area| area.iter_streets_prefixed("ha".into(), None, &world))
+                .count();
+            let prefix = start.elapsed();
+            let start = std::time::Instant::now();
+            for area in areas.iter() {
+                let last = area.iter_streets().last().unwrap().name(&world);
+                assert!(area.get_street(&last, &world).is_some());
+            }
+            let lookup = start.elapsed();
+            println!(
+                "{}: {} street names in {} bytes of {} bytes, {} postal areas; \
+                 listing all streets took {:.2?}, {} with a prefix {:.2?}, looking up one street each {:.2?}",
+                layout,
+                world.streets.names().len(),
+                pool_size,
+                world_size,
+                areas.len(),
+                listing,
+                prefixed,
+                prefix,
+                lookup
+            );
+            match names.is_empty() {
+                true => names = listed,
+                false => assert_eq!(names, listed),
+            }
+        }
+    }
 }
@@ -0,0 +1,520 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    ops::AddAssign,
+    path::PathBuf,
+};
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    compress::{City, Country, District, PostalArea, Street, World},
+    serve::parse_into_world,
+};
+
+/// Number of entries per level, including everything nested below.
+#[derive(Default, Debug, PartialEq)]
+pub struct Tally {
+    pub countries: usize,
+    pub cities: usize,
+    pub areas: usize,
+    pub streets: usize,
+    pub housenumbers: usize,
+}
+
+impl AddAssign for Tally {
+    fn add_assign(&mut self, rhs: Self) {
+        self.countries += rhs.countries;
+        self.cities += rhs.cities;
+        self.areas += rhs.areas;
+        self.streets += rhs.streets;
+        self.housenumbers += rhs.housenumbers;
+    }
+}
+
+fn tally_street(street: &Street) -> Tally {
+    Tally {
+        streets: 1,
+        housenumbers: street.housenumber_count(),
+        ..Default::default()
+    }
+}
+
+fn tally_area(area: &PostalArea) -> Tally {
+    let mut tally = Tally {
+        areas: 1,
+        ..Default::default()
+    };
+    for street in area.iter_streets() {
+        tally += tally_street(street);
+    }
+    tally
+}
+
+fn tally_district(district: &District) -> Tally {
+    let mut tally = Tally::default();
+    for area in district.iter_zips() {
+        tally += tally_area(area);
+    }
+    tally
+}
+
+/// Postal codes of a city, a postal area spanning several districts once.
+fn city_zips(city: &City) -> BTreeSet<&str> {
+    city.iter_zips().map(|area| area.code.as_str()).collect()
+}
+
+fn tally_city(city: &City) -> Tally {
+    let mut tally = Tally {
+        cities: 1,
+        ..Default::default()
+    };
+    for district in city.iter_districts() {
+        tally += tally_district(district);
+    }
+    tally.areas = city_zips(city).len();
+    tally
+}
+
+fn tally_country(country: &Country) -> Tally {
+    let mut tally = Tally {
+        countries: 1,
+        ..Default::default()
+    };
+    for city in country.iter_cities() {
+        tally += tally_city(city);
+    }
+    tally
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Removed,
+}
+
+#[derive(Serialize, Default, Clone)]
+struct Location<'a> {
+    country: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    city: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    district: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zip: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    street: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    housenumber: Option<&'a str>,
+}
+
+impl<'a> Location<'a> {
+    fn level(&self) -> &'static str {
+        match self {
+            Location {
+                housenumber: Some(_),
+                ..
+            } => "housenumber",
+            Location {
+                street: Some(_), ..
+            } => "street",
+            Location { zip: Some(_), .. } => "zip",
+            Location {
+                district: Some(_), ..
+            } => "district",
+            Location { city: Some(_), .. } => "city",
+            _ => "country",
+        }
+    }
+}
+
+/// One line of the JSONL listing.
+#[derive(Serialize)]
+struct Record<'a> {
+    change: Change,
+    level: &'static str,
+    #[serde(flatten)]
+    location: Location<'a>,
+    /// House numbers affected by this change, including nested ones.
+    housenumbers: usize,
+}
+
+enum Pair<T> {
+    Removed(T),
+    Added(T),
+    Both(T, T),
+}
+
+/// Merge join two iterators, both sorted ascending by `key`, lazily.
+fn join_sorted<T, K: Ord>(
+    old: impl Iterator<Item = T>,
+    new: impl Iterator<Item = T>,
+    key: impl Fn(&T) -> K,
+) -> impl Iterator<Item = Pair<T>> {
+    let mut old = old.peekable();
+    let mut new = new.peekable();
+    std::iter::from_fn(move || {
+        let order = match (old.peek(), new.peek()) {
+            (None, None) => return None,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(o), Some(n)) => key(o).cmp(&key(n)),
+        };
+        Some(match order {
+            std::cmp::Ordering::Less => Pair::Removed(old.next().unwrap()),
+            std::cmp::Ordering::Greater => Pair::Added(new.next().unwrap()),
+            std::cmp::Ordering::Equal => Pair::Both(old.next().unwrap(), new.next().unwrap()),
+        })
+    })
+}
+
+/// Walks two worlds side by side. Both are sorted on every level,
+/// so no level has to be held in memory as a whole.
+struct Differ<'a, W: Write> {
+    old: &'a World,
+    new: &'a World,
+    added: Tally,
+    removed: Tally,
+    listing: Option<W>,
+}
+
+impl<'a, W: Write> Differ<'a, W> {
+    fn record(&mut self, change: Change, location: Location, tally: Tally) -> Result<(), String> {
+        if let Some(listing) = &mut self.listing {
+            let record = Record {
+                change,
+                level: location.level(),
+                housenumbers: tally.housenumbers,
+                location,
+            };
+            serde_json::to_writer(&mut *listing, &record).map_err(|e| e.to_string())?;
+            listing
+                .write_all("\n".as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        match change {
+            Change::Added => self.added += tally,
+            Change::Removed => self.removed += tally,
+        }
+        Ok(())
+    }
+
+    fn diff_countries(&mut self) -> Result<(), String> {
+        let (old, new) = (self.old, self.new);
+        for pair in join_sorted(old.iter_countries(), new.iter_countries(), |c| {
+            c.code.clone()
+        }) {
+            let (change, country) = match pair {
+                Pair::Removed(c) => (Change::Removed, c),
+                Pair::Added(c) => (Change::Added, c),
+                Pair::Both(o, n) => {
+                    self.diff_cities(o, n)?;
+                    continue;
+                }
+            };
+            let location = Location {
+                country: &country.code,
+                ..Default::default()
+            };
+            self.record(change, location, tally_country(country))?;
+        }
+        Ok(())
+    }
+
+    fn diff_cities(&mut self, old: &Country, new: &Country) -> Result<(), String> {
+        for pair in join_sorted(old.iter_cities(), new.iter_cities(), |c| c.name.clone()) {
+            let (change, city) = match pair {
+                Pair::Removed(c) => (Change::Removed, c),
+                Pair::Added(c) => (Change::Added, c),
+                Pair::Both(o, n) => {
+                    self.diff_districts(&old.code, o, n)?;
+                    continue;
+                }
+            };
+            let location = Location {
+                country: &old.code,
+                city: Some(&city.name),
+                ..Default::default()
+            };
+            self.record(change, location, tally_city(city))?;
+        }
+        Ok(())
+    }
+
+    /// Postal areas are counted once per city afterwards, as they may span several districts.
+    fn diff_districts(&mut self, country: &str, old: &City, new: &City) -> Result<(), String> {
+        for pair in join_sorted(old.iter_districts(), new.iter_districts(), |d| {
+            d.name.clone()
+        }) {
+            let (change, district) = match pair {
+                Pair::Removed(d) => (Change::Removed, d),
+                Pair::Added(d) => (Change::Added, d),
+                Pair::Both(o, n) => {
+                    let location = Location {
+                        country,
+                        city: Some(&old.name),
+                        district: Some(o.name.as_str()).filter(|d| !d.is_empty()),
+                        ..Default::default()
+                    };
+                    self.diff_areas(location, o, n)?;
+                    continue;
+                }
+            };
+            let location = Location {
+                country,
+                city: Some(&old.name),
+                ..Default::default()
+            };
+            if district.name.is_empty() {
+                // Addresses without district are listed by postal area.
+                for area in district.iter_zips() {
+                    let location = Location {
+                        zip: Some(&area.code),
+                        ..location.clone()
+                    };
+                    let tally = Tally {
+                        areas: 0,
+                        ..tally_area(area)
+                    };
+                    self.record(change, location, tally)?;
+                }
+            } else {
+                let location = Location {
+                    district: Some(&district.name),
+                    ..location
+                };
+                let tally = Tally {
+                    areas: 0,
+                    ..tally_district(district)
+                };
+                self.record(change, location, tally)?;
+            }
+        }
+        let (old_zips, new_zips) = (city_zips(old), city_zips(new));
+        self.added.areas += new_zips.difference(&old_zips).count();
+        self.removed.areas += old_zips.difference(&new_zips).count();
+        Ok(())
+    }
+
+    fn diff_areas(
+        &mut self,
+        location: Location,
+        old: &District,
+        new: &District,
+    ) -> Result<(), String> {
+        for pair in join_sorted(old.iter_zips(), new.iter_zips(), |a| a.code.clone()) {
+            let (change, area) = match pair {
+                Pair::Removed(a) => (Change::Removed, a),
+                Pair::Added(a) => (Change::Added, a),
+                Pair::Both(o, n) => {
+                    let location = Location {
+                        zip: Some(&o.code),
+                        ..location.clone()
+                    };
+                    self.diff_streets(location, o, n)?;
+                    continue;
+                }
+            };
+            let location = Location {
+                zip: Some(&area.code),
+                ..location.clone()
+            };
+            let tally = Tally {
+                areas: 0,
+                ..tally_area(area)
+            };
+            self.record(change, location, tally)?;
+        }
+        Ok(())
+    }
+
+    fn diff_streets(
+        &mut self,
+        location: Location,
+        old: &PostalArea,
+        new: &PostalArea,
+    ) -> Result<(), String> {
+        let (old_world, new_world) = (self.old, self.new);
+        let old_streets = old.iter_streets().map(|s| (s.name(old_world), s));
+        let new_streets = new.iter_streets().map(|s| (s.name(new_world), s));
+        for pair in join_sorted(old_streets, new_streets, |(name, _)| name.clone()) {
+            let (change, (name, street)) = match pair {
+                Pair::Removed(s) => (Change::Removed, s),
+                Pair::Added(s) => (Change::Added, s),
+                Pair::Both((name, o), (_, n)) => {
+                    let location = Location {
+                        street: Some(&name),
+                        ..location.clone()
+                    };
+                    self.diff_housenumbers(location, o, n)?;
+                    continue;
+                }
+            };
+            let location = Location {
+                street: Some(&name),
+                ..location.clone()
+            };
+            self.record(change, location, tally_street(street))?;
+        }
+        Ok(())
+    }
+
+    fn diff_housenumbers(
+        &mut self,
+        location: Location,
+        old: &Street,
+        new: &Street,
+    ) -> Result<(), String> {
+        let old_hns = old.housenumber_iter(self.old);
+        let new_hns = new.housenumber_iter(self.new);
+        for pair in join_sorted(old_hns, new_hns, |hn| hn.clone()) {
+            let (change, hn) = match pair {
+                Pair::Removed(hn) => (Change::Removed, hn),
+                Pair::Added(hn) => (Change::Added, hn),
+                Pair::Both(_, _) => continue,
+            };
+            let location = Location {
+                housenumber: Some(&hn),
+                ..location.clone()
+            };
+            let tally = Tally {
+                housenumbers: 1,
+                ..Default::default()
+            };
+            self.record(change, location, tally)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare two worlds, returning what was added and what was removed.
+/// If `listing` is given, every change is written to it as a json line.
+/// Removed or added entries are listed on the highest level only,
+/// e.g. a removed city does not list its streets again.
+pub fn diff(old: &World, new: &World, listing: Option<impl Write>) -> Result<(Tally, Tally), String> {
+    let mut differ = Differ {
+        old,
+        new,
+        added: Tally::default(),
+        removed: Tally::default(),
+        listing,
+    };
+    differ.diff_countries()?;
+    Ok((differ.added, differ.removed))
+}
+
+pub fn diff_worlds(old: PathBuf, new: PathBuf, list: bool) -> Result<(), String> {
+    let old = parse_into_world(old)?;
+    let new = parse_into_world(new)?;
+    info!("Comparing worlds...");
+    let listing = match list {
+        true => Some(io::stdout().lock()),
+        false => None,
+    };
+    let (added, removed) = diff(&old, &new, listing)?;
+    for (level, added, removed) in [
+        ("countries", added.countries, removed.countries),
+        ("cities", added.cities, removed.cities),
+        ("postal areas", added.areas, removed.areas),
+        ("streets", added.streets, removed.streets),
+        ("house numbers", added.housenumbers, removed.housenumbers),
+    ] {
+        info!("{:>14}: +{} -{}", level, added, removed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::compress::{test::world_of, Provenance};
+
+    #[test]
+    fn diff_levels() {
+        let old = world_of(&[
+            ("DE", "Berlin", "10115", "Invalidenstraße", "1"),
+            ("DE", "Berlin", "10115", "Invalidenstraße", "2a"),
+            ("DE", "Potsdam", "14467", "Breite Straße", "3"),
+            ("DE", "Potsdam", "14467", "Breite Straße", "4"),
+        ]);
+        let new = world_of(&[
+            ("DE", "Berlin", "10115", "Invalidenstraße", "1"),
+            ("DE", "Berlin", "10115", "Invalidenstraße", "3"),
+            ("DE", "Berlin", "10117", "Unter den Linden", "5"),
+            ("FR", "Lyon", "69001", "Rue de la République", "4"),
+        ]);
+        let mut listing: Vec<u8> = Vec::new();
+        let (added, removed) = diff(&old, &new, Some(&mut listing)).unwrap();
+        assert_eq!(
+            added,
+            Tally {
+                countries: 1,
+                cities: 1,
+                areas: 2,
+                streets: 2,
+                housenumbers: 3,
+            }
+        );
+        assert_eq!(
+            removed,
+            Tally {
+                countries: 0,
+                cities: 1,
+                areas: 1,
+                streets: 1,
+                housenumbers: 3,
+            }
+        );
+        let listing = String::from_utf8(listing).unwrap();
+        assert_eq!(listing.lines().count(), 5);
+        assert!(listing.contains(
+            r#"{"change":"removed","level":"city","country":"DE","city":"Potsdam","housenumbers":2}"#
+        ));
+    }
+
+    #[test]
+    fn areas_spanning_districts() {
+        let district = |district: &str, zip: &str, street: &str| {
+            (district.to_string(), zip.to_string(), street.to_string())
+        };
+        let world = |addresses: &[(String, String, String)]| {
+            let streets: HashSet<String> = addresses.iter().map(|a| a.2.clone()).collect();
+            let mut world = World::new(streets.into(), HashSet::new().into());
+            for (co, ci) in [("DE", "Berlin"), ("DE", "Hamburg")] {
+                for (district, zip, street) in addresses {
+                    world.insert_address(
+                        co.into(),
+                        ci.into(),
+                        Some(district.clone()),
+                        zip.clone(),
+                        street.clone(),
+                        "1".into(),
+                        None,
+                        None,
+                        Provenance::ORIGINAL,
+                    );
+                }
+            }
+            world.sort();
+            world
+        };
+        let old = world(&[
+            district("Mitte", "10115", "Invalidenstraße"),
+            district("Wedding", "10115", "Gartenstraße"),
+        ]);
+        let new = world(&[
+            district("Mitte", "10117", "Unter den Linden"),
+            district("Moabit", "10115", "Invalidenstraße"),
+            district("Wedding", "10115", "Gartenstraße"),
+        ]);
+        let (added, removed) = diff(&old, &new, None::<Vec<u8>>).unwrap();
+        // 10115 is still part of both cities, 10117 was added to both.
+        assert_eq!((added.areas, removed.areas), (2, 0));
+        let (added, removed) = diff(&World::merge(vec![]), &new, None::<Vec<u8>>).unwrap();
+        assert_eq!((added.cities, added.areas, removed.areas), (2, 4, 0));
+    }
+}
@@ -0,0 +1,608 @@
+//! External merge sort, so `macs compress` does not need to hold all addresses in memory.
+//! Items are buffered up to a memory limit, then spilled as a sorted run to a temporary file.
+//! The runs are merged again while building the world.
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap},
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use log::info;
+use serde::{de::DeserializeOwned, Serialize};
+use tempfile::TempDir;
+
+use crate::{
+    autofix::Located,
+    compress::Provenance,
+    parse::{Address, IncompleteAddress},
+};
+
+/// Parse a size like `512M`, `2GiB` or `1000000` into bytes.
+pub fn parse_bytes(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let value: usize = s[..digits]
+        .parse()
+        .map_err(|_| format!("Invalid size \"{}\"", s))?;
+    let unit = match s[digits..]
+        .trim()
+        .to_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "Invalid size unit in \"{}\", expected K, M, G or T",
+                s
+            ))
+        }
+    };
+    Ok(value * unit)
+}
+
+/// Rough number of bytes names in other languages occupy in memory.
+fn names_size(names: &BTreeMap<String, String>) -> usize {
+    names
+        .iter()
+        .map(|(lang, name)| lang.len() + name.len() + 64)
+        .sum::<usize>()
+}
+
+/// Rough number of bytes an address occupies in memory.
+fn estimated_size(a: &Address) -> usize {
+    let strings = [&a.country, &a.city, &a.postcode, &a.street, &a.housenumber]
+        .iter()
+        .map(|s| s.len())
+        .sum::<usize>();
+    let optional = [&a.district, &a.unit]
+        .iter()
+        .filter_map(|s| s.as_ref().map(|s| s.len()))
+        .sum::<usize>();
+    size_of::<Address>()
+        + strings
+        + optional
+        + names_size(&a.street_names)
+        + names_size(&a.city_names)
+}
+
+fn estimated_incomplete_size(a: &IncompleteAddress) -> usize {
+    let strings = [
+        &a.country,
+        &a.city,
+        &a.district,
+        &a.postcode,
+        &a.street,
+        &a.housenumber,
+        &a.unit,
+    ]
+    .iter()
+    .filter_map(|s| s.as_ref().map(|s| s.len()))
+    .sum::<usize>();
+    size_of::<IncompleteAddress>()
+        + strings
+        + names_size(&a.street_names)
+        + names_size(&a.city_names)
+}
+
+/// Order in which addresses are inserted into the world.
+/// Addresses only differing in their names in other languages keep their input order,
+/// as the sorts are stable and runs are merged oldest first.
+fn compare(a: &Address, b: &Address) -> Ordering {
+    (
+        &a.country,
+        &a.city,
+        &a.district,
+        &a.postcode,
+        &a.street,
+        &a.housenumber,
+        &a.unit,
+    )
+        .cmp(&(
+            &b.country,
+            &b.city,
+            &b.district,
+            &b.postcode,
+            &b.street,
+            &b.housenumber,
+            &b.unit,
+        ))
+        .then_with(|| a.id.cmp(&b.id))
+        .then_with(|| (a.long, a.lat).cmp(&(b.long, b.lat)))
+}
+
+/// Largest number of runs merged at once, so the number of open files stays bounded.
+/// More runs are merged in several passes, oldest first.
+const FAN_IN: usize = 64;
+
+/// Sorts items of any size, spilling sorted runs to temporary files once they exceed the memory limit.
+/// Items are written as json lines, like the input of `macs compress`.
+/// Order of incomplete addresses, by street first.
+fn compare_incomplete(a: &IncompleteAddress, b: &IncompleteAddress) -> Ordering {
+    (
+        &a.street,
+        &a.country,
+        &a.city,
+        &a.district,
+        &a.postcode,
+        &a.housenumber,
+        &a.unit,
+    )
+        .cmp(&(
+            &b.street,
+            &b.country,
+            &b.city,
+            &b.district,
+            &b.postcode,
+            &b.housenumber,
+            &b.unit,
+        ))
+        .then_with(|| a.id.cmp(&b.id))
+        .then_with(|| (a.long, a.lat).cmp(&(b.long, b.lat)))
+}
+
+pub struct ExternalSorter<T> {
+    memory_limit: usize,
+    compare: fn(&T, &T) -> Ordering,
+    size: fn(&T) -> usize,
+    /// Whether the second of two adjacent items is dropped.
+    duplicate: fn(&T, &T) -> bool,
+    fan_in: usize,
+    buffer: Vec<T>,
+    buffer_size: usize,
+    dir: Option<TempDir>,
+    runs: Vec<PathBuf>,
+    spilled: usize,
+    len: usize,
+    duplicates: usize,
+}
+
+impl ExternalSorter<Address> {
+    /// Addresses in the order they are inserted into the world.
+    /// Of equal addresses of the same OSM id, e.g. contained in overlapping extracts, only one is kept.
+    pub fn addresses(memory_limit: Option<usize>) -> Self {
+        Self::new(memory_limit, compare, estimated_size)
+            .dedup_by(|a, b| a.id.is_some() && compare(a, b) == Ordering::Equal)
+    }
+}
+
+impl ExternalSorter<IncompleteAddress> {
+    /// Incomplete addresses by street, so the autofixer only needs the locations of some streets at once.
+    pub fn incomplete_addresses(memory_limit: Option<usize>) -> Self {
+        Self::new(memory_limit, compare_incomplete, estimated_incomplete_size)
+            .dedup_by(|a, b| a.id.is_some() && compare_incomplete(a, b) == Ordering::Equal)
+    }
+}
+
+impl ExternalSorter<Located> {
+    /// Locations of complete addresses by street, like [ExternalSorter::incomplete_addresses].
+    pub fn locations(memory_limit: Option<usize>) -> Self {
+        Self::new(
+            memory_limit,
+            |a, b| (&a.street, &a.area, a.long, a.lat).cmp(&(&b.street, &b.area, b.long, b.lat)),
+            |l| {
+                size_of::<Located>()
+                    + l.street.len()
+                    + l.area.0.len()
+                    + l.area.1.len()
+                    + l.area.2.len()
+            },
+        )
+    }
+}
+
+impl ExternalSorter<(Address, Provenance)> {
+    /// Addresses completed by the autofixer, inserted once all are fixed.
+    pub fn fixed_addresses(memory_limit: Option<usize>) -> Self {
+        Self::new(
+            memory_limit,
+            |a, b| compare(&a.0, &b.0),
+            |(a, _)| estimated_size(a) + size_of::<Provenance>(),
+        )
+    }
+}
+
+impl ExternalSorter<(String, String, BTreeMap<String, String>)> {
+    /// Names of streets in other languages by country, street and language, without duplicates.
+    pub fn street_names(memory_limit: Option<usize>) -> Self {
+        Self::new(memory_limit, Ord::cmp, |(country, street, names)| {
+            size_of::<(String, String, BTreeMap<String, String>)>()
+                + country.len()
+                + street.len()
+                + names_size(names)
+        })
+        .dedup_by(|a, b| a == b)
+    }
+}
+
+impl ExternalSorter<String> {
+    /// Unique strings, e.g. for the pool of street names.
+    pub fn strings(memory_limit: Option<usize>) -> Self {
+        Self::new(memory_limit, String::cmp, |s| size_of::<String>() + s.len())
+            .dedup_by(|a, b| a == b)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> ExternalSorter<T> {
+    /// Spill to temporary files once the buffered items exceed `memory_limit` bytes, as estimated by `size`.
+    pub fn new(
+        memory_limit: Option<usize>,
+        compare: fn(&T, &T) -> Ordering,
+        size: fn(&T) -> usize,
+    ) -> Self {
+        Self {
+            memory_limit: memory_limit.unwrap_or(usize::MAX),
+            compare,
+            size,
+            duplicate: |_, _| false,
+            fan_in: FAN_IN,
+            buffer: Vec::new(),
+            buffer_size: 0,
+            dir: None,
+            runs: Vec::new(),
+            spilled: 0,
+            len: 0,
+            duplicates: 0,
+        }
+    }
+
+    /// Keep only the first of adjacent items for which `duplicate` holds.
+    pub fn dedup_by(mut self, duplicate: fn(&T, &T) -> bool) -> Self {
+        self.duplicate = duplicate;
+        self
+    }
+
+    /// Number of pushed items, including duplicates.
+    pub fn count(&self) -> usize {
+        self.len
+    }
+
+    pub fn push(&mut self, item: T) -> Result<(), String> {
+        self.buffer_size += (self.size)(&item);
+        self.buffer.push(item);
+        self.len += 1;
+        if self.buffer_size > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Sort and deduplicate the buffer.
+    fn sort_buffer(&mut self) {
+        let (compare, duplicate) = (self.compare, self.duplicate);
+        self.buffer.sort_by(compare);
+        let before = self.buffer.len();
+        self.buffer
+            .dedup_by(|later, earlier| duplicate(earlier, later));
+        self.duplicates += before - self.buffer.len();
+    }
+
+    fn next_run_path(&mut self) -> Result<PathBuf, String> {
+        if self.dir.is_none() {
+            self.dir = Some(tempfile::tempdir().map_err(|e| e.to_string())?);
+        }
+        let path = self
+            .dir
+            .as_ref()
+            .unwrap()
+            .path()
+            .join(format!("run-{}.jsonl", self.spilled));
+        self.spilled += 1;
+        Ok(path)
+    }
+
+    fn spill(&mut self) -> Result<(), String> {
+        let path = self.next_run_path()?;
+        info!("Spilling {} items to {:?}...", self.buffer.len(), path);
+        self.sort_buffer();
+        write_run(&path, self.buffer.drain(..).map(Ok))?;
+        self.buffer.shrink_to_fit();
+        self.buffer_size = 0;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Merge of the given runs and the buffer, which is sorted and reversed.
+    fn merge(&self, runs: &[PathBuf], buffer: Vec<T>) -> Result<Sorted<T>, String> {
+        let runs = runs
+            .iter()
+            .map(|path| {
+                File::open(path)
+                    .map(|file| BufReader::new(file).lines())
+                    .map_err(|e| format!("Could not open sorted run {:?}: {}", path, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let mut sorted = Sorted {
+            compare: self.compare,
+            duplicate: self.duplicate,
+            buffer,
+            popped: 0,
+            runs,
+            heap: BinaryHeap::new(),
+            duplicates: 0,
+            _dir: None,
+        };
+        for run in 0..sorted.runs.len() {
+            if let Some(e) = sorted.refill(run) {
+                return Err(format!("Could not read sorted run: {}", e));
+            }
+        }
+        if !sorted.buffer.is_empty() && !sorted.runs.is_empty() {
+            let item = sorted.buffer.pop().unwrap();
+            sorted.heap.push(Reverse(Head {
+                item,
+                run: None,
+                compare: self.compare,
+            }));
+        }
+        Ok(sorted)
+    }
+
+    /// All pushed items, in sorted order.
+    pub fn finish(mut self) -> Result<Sorted<T>, String> {
+        while self.runs.len() > self.fan_in {
+            let oldest: Vec<PathBuf> = self.runs.drain(..self.fan_in).collect();
+            let path = self.next_run_path()?;
+            info!("Merging {} sorted runs into {:?}...", oldest.len(), path);
+            let mut merged = self.merge(&oldest, Vec::new())?;
+            write_run(&path, &mut merged)?;
+            self.duplicates += merged.duplicates;
+            for run in oldest {
+                fs::remove_file(&run)
+                    .map_err(|e| format!("Could not remove sorted run {:?}: {}", run, e))?;
+            }
+            // The merged runs are older than the remaining ones.
+            self.runs.insert(0, path);
+        }
+        self.sort_buffer();
+        // Reversed, so the memory is freed while popping from the end.
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.reverse();
+        let mut sorted = self.merge(&self.runs, buffer)?;
+        sorted.duplicates = self.duplicates;
+        sorted._dir = self.dir;
+        Ok(sorted)
+    }
+}
+
+fn write_run<T: Serialize>(
+    path: &Path,
+    items: impl Iterator<Item = Result<T, String>>,
+) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("Could not create sorted run {:?}: {}", path, e))?;
+    let mut writer = BufWriter::new(file);
+    for item in items {
+        serde_json::to_writer(&mut writer, &item?).map_err(|e| e.to_string())?;
+        writer
+            .write_all("\n".as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Next item of a run, ordered to be used in a min-heap.
+struct Head<T> {
+    item: T,
+    /// `None` for the in-memory buffer, which holds the newest items.
+    run: Option<usize>,
+    compare: fn(&T, &T) -> Ordering,
+}
+
+impl<T> PartialEq for Head<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Head<T> {}
+
+impl<T> PartialOrd for Head<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Head<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let age = |run: Option<usize>| run.unwrap_or(usize::MAX);
+        (self.compare)(&self.item, &other.item).then_with(|| age(self.run).cmp(&age(other.run)))
+    }
+}
+
+pub struct Sorted<T> {
+    compare: fn(&T, &T) -> Ordering,
+    duplicate: fn(&T, &T) -> bool,
+    buffer: Vec<T>,
+    popped: usize,
+    runs: Vec<Lines<BufReader<File>>>,
+    heap: BinaryHeap<Reverse<Head<T>>>,
+    duplicates: usize,
+    /// Removes the runs once dropped.
+    _dir: Option<TempDir>,
+}
+
+impl<T: DeserializeOwned> Sorted<T> {
+    /// Number of dropped duplicates, complete once all items were read.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    fn pop_buffer(&mut self) -> Option<T> {
+        self.popped += 1;
+        if self.popped % 100_000 == 0 {
+            self.buffer.shrink_to_fit();
+        }
+        self.buffer.pop()
+    }
+
+    fn refill(&mut self, run: usize) -> Option<String> {
+        match self.runs[run].next() {
+            None => None,
+            Some(Err(e)) => Some(e.to_string()),
+            Some(Ok(line)) => match serde_json::from_str::<T>(&line) {
+                Ok(item) => {
+                    self.heap.push(Reverse(Head {
+                        item,
+                        run: Some(run),
+                        compare: self.compare,
+                    }));
+                    None
+                }
+                Err(e) => Some(e.to_string()),
+            },
+        }
+    }
+
+    fn peek(&self) -> Option<&T> {
+        if self.runs.is_empty() {
+            return self.buffer.last();
+        }
+        self.heap.peek().map(|Reverse(head)| &head.item)
+    }
+
+    fn pop(&mut self) -> Option<Result<T, String>> {
+        if self.runs.is_empty() {
+            return self.pop_buffer().map(Ok);
+        }
+        let Reverse(head) = self.heap.pop()?;
+        let error = match head.run {
+            Some(run) => self.refill(run),
+            None => {
+                if let Some(item) = self.pop_buffer() {
+                    self.heap.push(Reverse(Head {
+                        item,
+                        run: None,
+                        compare: self.compare,
+                    }));
+                }
+                None
+            }
+        };
+        match error {
+            Some(e) => Some(Err(format!("Could not read sorted run: {}", e))),
+            None => Some(Ok(head.item)),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for Sorted<T> {
+    type Item = Result<T, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.pop()?;
+        if let Ok(item) = &item {
+            while self.peek().is_some_and(|next| (self.duplicate)(item, next)) {
+                self.duplicates += 1;
+                if let Some(Err(e)) = self.pop() {
+                    return Some(Err(e));
+                }
+            }
+        }
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(city: &str, street: &str, housenumber: &str) -> Address {
+        Address {
+            country: "NL".into(),
+            city: city.into(),
+            postcode: "1000 AA".into(),
+            street: street.into(),
+            housenumber: housenumber.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_spilled_runs() {
+        let mut sorter = ExternalSorter::addresses(Some(1));
+        let mut in_memory = ExternalSorter::addresses(None);
+        let addresses = [
+            ("Utrecht", "Oudegracht", "7"),
+            ("Amsterdam", "Damrak", "1"),
+            ("Utrecht", "Neude", "11"),
+            ("Amsterdam", "Damrak", "10"),
+            ("Delft", "Markt", "87"),
+        ];
+        for (city, street, hn) in addresses {
+            sorter.push(address(city, street, hn)).unwrap();
+            in_memory.push(address(city, street, hn)).unwrap();
+        }
+        // Every address exceeds the limit of one byte, so each is spilled into its own run.
+        assert_eq!(sorter.runs.len(), 5);
+        assert_eq!(sorter.count(), 5);
+        let key = |a: Address| (a.city, a.street, a.housenumber);
+        let spilled: Vec<_> = sorter.finish().unwrap().map(|a| key(a.unwrap())).collect();
+        let sorted: Vec<_> = in_memory
+            .finish()
+            .unwrap()
+            .map(|a| key(a.unwrap()))
+            .collect();
+        assert_eq!(spilled, sorted);
+        assert_eq!(spilled[0].0, "Amsterdam");
+        assert_eq!(spilled[1].2, "10");
+        assert_eq!(spilled[4].1, "Oudegracht");
+        assert_eq!(parse_bytes("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_bytes("512M"), Ok(512 << 20));
+        assert!(parse_bytes("lots").is_err());
+    }
+
+    #[test]
+    fn merge_in_passes() {
+        let mut sorter = ExternalSorter::strings(Some(1));
+        sorter.fan_in = 2;
+        for street in [
+            "Neude",
+            "Damrak",
+            "Markt",
+            "Damrak",
+            "Oudegracht",
+            "Neude",
+            "Markt",
+        ] {
+            sorter.push(street.to_string()).unwrap();
+        }
+        assert_eq!(sorter.runs.len(), 7);
+        let mut sorted = sorter.finish().unwrap();
+        assert_eq!(sorted.runs.len(), 2);
+        let streets: Vec<String> = sorted.by_ref().map(|s| s.unwrap()).collect();
+        assert_eq!(streets, vec!["Damrak", "Markt", "Neude", "Oudegracht"]);
+        assert_eq!(sorted.duplicates(), 3);
+    }
+
+    #[test]
+    fn duplicate_osm_ids() {
+        use osmpbfreader::{NodeId, OsmId};
+        let mut sorter = ExternalSorter::addresses(Some(1 << 10));
+        let with_id = |hn: &str, id: i64| Address {
+            id: Some(OsmId::Node(NodeId(id))),
+            ..address("Utrecht", "Neude", hn)
+        };
+        for a in [
+            with_id("11", 1),
+            address("Utrecht", "Neude", "12"),
+            with_id("11", 1),
+            address("Utrecht", "Neude", "12"),
+            // Same id in another version of the object.
+            with_id("13", 1),
+        ] {
+            sorter.push(a).unwrap();
+        }
+        let mut sorted = sorter.finish().unwrap();
+        let housenumbers: Vec<String> = sorted.by_ref().map(|a| a.unwrap().housenumber).collect();
+        assert_eq!(housenumbers, vec!["11", "12", "12", "13"]);
+        assert_eq!(sorted.duplicates(), 1);
+    }
+}
@@ -0,0 +1,163 @@
+//! Front coded pool of sorted strings. Sorted street names share long prefixes,
+//! like `Hauptstraße` and `Hauptweg`, so each string only stores what differs from the previous one.
+//! Strings are grouped in blocks, every block starts with a complete string to search in.
+use serde::{Deserialize, Serialize};
+
+/// Strings per block. Larger blocks are smaller, but slower to look up.
+const BLOCK_SIZE: usize = 16;
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn shared_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct FrontCoded {
+    /// Per string the length of the prefix shared with the previous one,
+    /// the length of the rest and the rest itself.
+    bytes: Vec<u8>,
+    /// Offset of every block in `bytes`.
+    blocks: Vec<u32>,
+    len: u32,
+}
+
+impl FrontCoded {
+    /// Encode strings, which have to be sorted and unique.
+    pub fn new<S: AsRef<str>>(sorted: impl IntoIterator<Item = S>) -> Self {
+        let mut pool = Self::default();
+        let mut previous: Vec<u8> = Vec::new();
+        for s in sorted {
+            let s = s.as_ref().as_bytes();
+            let shared = match pool.len as usize % BLOCK_SIZE {
+                0 => {
+                    pool.blocks.push(pool.bytes.len() as u32);
+                    0
+                }
+                _ => shared_prefix(&previous, s),
+            };
+            write_varint(&mut pool.bytes, shared);
+            write_varint(&mut pool.bytes, s.len() - shared);
+            pool.bytes.extend_from_slice(&s[shared..]);
+            previous.clear();
+            previous.extend_from_slice(s);
+            pool.len += 1;
+        }
+        pool
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Decode the strings of a block up to and including the `n`th one, calling `f` with each.
+    /// Stops early if `f` returns false.
+    fn scan_block(&self, block: usize, n: usize, mut f: impl FnMut(&[u8]) -> bool) {
+        let mut pos = self.blocks[block] as usize;
+        let mut current: Vec<u8> = Vec::new();
+        for _ in 0..=n {
+            let shared = read_varint(&self.bytes, &mut pos);
+            let rest = read_varint(&self.bytes, &mut pos);
+            current.truncate(shared);
+            current.extend_from_slice(&self.bytes[pos..pos + rest]);
+            pos += rest;
+            if !f(&current) {
+                return;
+            }
+        }
+    }
+
+    /// The first string of the block at `offset`, stored completely.
+    fn block_head(&self, offset: u32) -> &[u8] {
+        let mut pos = offset as usize;
+        read_varint(&self.bytes, &mut pos);
+        let rest = read_varint(&self.bytes, &mut pos);
+        &self.bytes[pos..pos + rest]
+    }
+
+    pub fn get(&self, i: usize) -> Option<String> {
+        if i >= self.len() {
+            return None;
+        }
+        let mut result = Vec::new();
+        self.scan_block(i / BLOCK_SIZE, i % BLOCK_SIZE, |s| {
+            result.clear();
+            result.extend_from_slice(s);
+            true
+        });
+        Some(String::from_utf8(result).expect("front coded pool contained invalid utf-8"))
+    }
+
+    pub fn index_of(&self, s: &str) -> Option<usize> {
+        let s = s.as_bytes();
+        // The last block starting with a string not greater than `s`.
+        let block = self
+            .blocks
+            .partition_point(|offset| self.block_head(*offset) <= s)
+            .checked_sub(1)?;
+        let in_block = (self.len() - block * BLOCK_SIZE).min(BLOCK_SIZE);
+        let mut found = None;
+        let mut n = 0;
+        self.scan_block(block, in_block - 1, |current| {
+            if current == s {
+                found = Some(block * BLOCK_SIZE + n);
+            }
+            n += 1;
+            current < s
+        });
+        found
+    }
+
+    /// All strings in order.
+    pub fn iter(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.len()).map(|i| self.get(i).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_and_look_up() {
+        let names: Vec<String> = (0..100)
+            .map(|i| format!("Haupt{}straße {}", ["", "weg", "ä"][i % 3], i))
+            .collect::<std::collections::BTreeSet<String>>()
+            .into_iter()
+            .collect();
+        let pool = FrontCoded::new(&names);
+        assert_eq!(pool.len(), 100);
+        let plain: usize = names.iter().map(|n| n.len()).sum();
+        assert!(pool.bytes.len() < plain / 2);
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(pool.get(i).as_ref(), Some(name));
+            assert_eq!(pool.index_of(name), Some(i));
+        }
+        assert_eq!(pool.iter().collect::<Vec<String>>(), names);
+        assert_eq!(pool.get(100), None);
+        assert_eq!(pool.index_of("Hauptstraße 100a"), None);
+        assert_eq!(pool.index_of("A"), None);
+        assert_eq!(pool.index_of("Z"), None);
+        assert_eq!(FrontCoded::new(Vec::<String>::new()).index_of("A"), None);
+    }
+}
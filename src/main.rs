@@ -1,20 +1,46 @@
 #![doc = include_str!("../README.md")]
-use std::{net::IpAddr, path::PathBuf};
+use std::{io, net::IpAddr, path::PathBuf};
 
 use clap::Parser;
 use log::{error, info};
 use parse::stdin_stdout_database;
 use serve::serve;
 
-use crate::{compress::read_and_compress, parse_coordinates::process_osm_pdf_to_stdout};
+use crate::{
+    abbreviations::Abbreviations,
+    address_store::update_store,
+    autofix::AutofixOptions,
+    compress::{read_and_compress, CompressOptions},
+    diff::diff_worlds,
+    external_sort::parse_bytes,
+    merge::merge_worlds,
+    osm_xml::open_maybe_compressed,
+    parse_coordinates::{
+        process_osm_pbf_with_node_store_to_stdout, process_osm_pdf_to_stdout,
+        process_osm_xml_to_stdout, spool_stdin, InputFormat, OutputOptions,
+    },
+    region::{BoundingBox, Polygon},
+};
 
+mod abbreviations;
+mod address_store;
 mod api;
+mod clean;
 mod compress;
+mod diff;
+mod external_sort;
+mod front_coded;
+mod merge;
+mod node_store;
+mod osm_id;
 mod parse;
+mod postcode;
 mod serve;
 mod sorted_vec;
 mod autofix;
+mod osm_xml;
 mod parse_coordinates;
+mod region;
 mod verbose_reader;
 
 
@@ -22,12 +48,38 @@ pub const MAX_ITEMS_HEADER: &str = "max-items";
 
 #[derive(Parser, Debug)]
 struct ParseParameters {
-    /// File n .osm.pbf format
+    /// File in .osm.pbf or OSM XML (.osm, .osm.bz2) format, stdin if not given
     #[arg(short, long)]
-    input: PathBuf,
+    input: Option<PathBuf>,
+    /// Format of the input, guessed by file extension if not given, PBF for stdin
+    #[arg(short, long, value_enum)]
+    format: Option<InputFormat>,
+    /// Directory to buffer PBF read from stdin in, system default if not given
+    #[arg(long)]
+    spool_dir: Option<PathBuf>,
     /// File n .osm.pbf format
     #[arg(short, long, default_value = "2048", help = "Try to use at most this amount of memory (in GiB)")]
     gib: f32,
+    /// Also write all addresses keyed by OSM id to this file, to be updated by `macs update`
+    #[arg(long)]
+    store: Option<PathBuf>,
+    /// Keep node locations in this file instead of memory, for planet-sized PBF input.
+    /// The file is sparse, indexed by node id, and removed when done.
+    #[arg(long)]
+    node_store: Option<PathBuf>,
+    /// Write objects no address could be computed for as json lines with the reason to this file
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+    /// Only keep addresses inside this bounding box, given as min_lon,min_lat,max_lon,max_lat
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<BoundingBox>,
+    /// Only keep addresses inside the polygon of this file (.poly or .geojson)
+    #[arg(long)]
+    polygon: Option<PathBuf>,
+    /// Complete names in other languages from places and streets of the same name (`name:fr`),
+    /// which takes another pass over PBF input and memory for all translated names
+    #[arg(long)]
+    name_variants: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -41,16 +93,85 @@ struct ServeParameters {
 }
 
 #[derive(Parser, Debug)]
-struct CompressParamters {}
+struct CompressParamters {
+    /// Keep the OSM id of every address in the world object
+    #[arg(long)]
+    osm_ids: bool,
+    /// Json file of abbreviations per country, like `{"DE": {"Str.": "Straße"}}`,
+    /// replacing the built-in table
+    #[arg(long)]
+    abbreviations: Option<PathBuf>,
+    /// Write values rejected by the cleaning rules (quoted, punctuation only, numeric cities, ...)
+    /// as json lines, e.g. to fix them in OpenStreetMap
+    #[arg(long)]
+    junk: Option<PathBuf>,
+    /// Number of nearest complete addresses on the same street to infer missing fields from
+    #[arg(long, default_value_t = AutofixOptions::default().neighbours)]
+    neighbours: usize,
+    /// Share of those neighbours which need to agree on the inferred fields,
+    /// fewer neighbours found count as disagreeing
+    #[arg(long, default_value_t = AutofixOptions::default().min_confidence)]
+    min_confidence: f64,
+    /// Neighbours further away, in meters, are not considered
+    #[arg(long, default_value_t = AutofixOptions::default().max_distance)]
+    max_distance: f64,
+    /// Write addresses which could not be completed as json lines, with the reason,
+    /// e.g. `{"reason": "ambiguous_city", "countries": 2, "address": {...}}`
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+    /// Memory for buffering addresses, locations and names, like `4G`, sorted runs are spilled to temporary files beyond that.
+    /// Unlimited if not given
+    #[arg(long, value_parser = parse_bytes)]
+    memory_limit: Option<usize>,
+    /// Store street names in a front coded pool per country instead of one global pool.
+    /// Smaller, with the names of a country close together, but every lookup decodes a block
+    #[arg(long)]
+    country_street_pools: bool,
+}
+
+#[derive(Parser, Debug)]
+struct UpdateParameters {
+    /// Address store written by `macs parse --store`, updated in place
+    #[arg(short, long)]
+    store: PathBuf,
+    /// osmChange files (.osc or .osc.gz), applied in the given order
+    #[arg(required = true)]
+    changes: Vec<PathBuf>,
+    /// Write all addresses of the updated store instead of the changed ones
+    #[arg(short, long)]
+    export: bool,
+}
+
+#[derive(Parser, Debug)]
+struct MergeParameters {
+    /// World files to merge
+    #[arg(required = true)]
+    worlds: Vec<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+struct DiffParameters {
+    old: PathBuf,
+    new: PathBuf,
+    /// Write every added or removed entry as json line to stdout
+    #[arg(short, long)]
+    list: bool,
+}
 
 #[derive(Parser, Debug)]
 enum Subcommand {
-    /// Parse a *.osm.pbf file, json lines will be written to stdout
+    /// Parse a *.osm.pbf or OSM XML file, json lines will be written to stdout
     Parse(ParseParameters),
     /// Read json lines, write compressed world object to stdout
     Compress(CompressParamters),
     /// Serve a world object via HTTP
     Serve(ServeParameters),
+    /// Apply OSM change files to an address store, changed addresses will be written to stdout
+    Update(UpdateParameters),
+    /// Merge multiple world objects, write merged world object to stdout
+    Merge(MergeParameters),
+    /// Compare two world objects, report added and removed entries
+    Diff(DiffParameters),
 }
 
 #[derive(Parser, Debug)]
@@ -78,22 +199,106 @@ fn setup_logger() {
         .unwrap()
 }
 
+fn parse(parameters: ParseParameters) -> Result<(), String> {
+    let format = match (parameters.format, &parameters.input) {
+        (Some(format), _) => format,
+        (None, Some(input)) => InputFormat::from_path(input),
+        (None, None) => InputFormat::Pbf,
+    };
+    let output = OutputOptions {
+        store: parameters.store,
+        rejects: parameters.rejects,
+        bbox: parameters.bbox,
+        polygon: match &parameters.polygon {
+            Some(path) => Some(Polygon::from_file(path)?),
+            None => None,
+        },
+        name_variants: parameters.name_variants,
+    };
+    match (format, parameters.input) {
+        (InputFormat::Xml, Some(input)) => {
+            info!("Reading OSM XML from {:?}...", input);
+            process_osm_xml_to_stdout(open_maybe_compressed(&input)?, output)
+        }
+        (InputFormat::Xml, None) => {
+            info!("Reading OSM XML from stdin...");
+            process_osm_xml_to_stdout(io::stdin().lock(), output)
+        }
+        (InputFormat::Pbf, Some(input)) => parse_pbf(
+            input,
+            parameters.gib,
+            parameters.node_store,
+            output,
+        ),
+        (InputFormat::Pbf, None) => {
+            // Resolving dependencies reads the input multiple times, which stdin does not allow.
+            let spooled = spool_stdin(parameters.spool_dir)?;
+            parse_pbf(
+                spooled.path().into(),
+                parameters.gib,
+                parameters.node_store,
+                output,
+            )
+        }
+    }
+}
+
+fn parse_pbf(
+    input: PathBuf,
+    gib: f32,
+    node_store: Option<PathBuf>,
+    output: OutputOptions,
+) -> Result<(), String> {
+    match node_store {
+        Some(node_store) => process_osm_pbf_with_node_store_to_stdout(input, node_store, output),
+        None => process_osm_pdf_to_stdout(input, gib, output),
+    }
+}
+
 fn main() -> Result<(), ()> {
     setup_logger();
     let args = Args::parse();
 
     match args.build {
-        Subcommand::Parse(parse) => {
-            info!("Reading osm.pbf from stdin...");
-            let x = process_osm_pdf_to_stdout(parse.input, parse.gib);
-            match x {
-                Err(e) => error!("Error: {}", e),
-                Ok(()) => info!("Done!"),
+        Subcommand::Parse(parameters) => match parse(parameters) {
+            Err(e) => error!("Error: {}", e),
+            Ok(()) => info!("Done!"),
+        },
+        Subcommand::Serve(parameters) => serve(parameters.world, parameters.ip, parameters.port),
+        Subcommand::Compress(parameters) => {
+            let abbreviations = match &parameters.abbreviations {
+                Some(path) => Abbreviations::from_file(path),
+                None => Ok(Abbreviations::defaults()),
+            };
+            let options = abbreviations.map(|abbreviations| CompressOptions {
+                keep_osm_ids: parameters.osm_ids,
+                abbreviations,
+                junk: parameters.junk,
+                autofix: AutofixOptions {
+                    neighbours: parameters.neighbours,
+                    min_confidence: parameters.min_confidence,
+                    max_distance: parameters.max_distance,
+                },
+                rejects: parameters.rejects,
+                memory_limit: parameters.memory_limit,
+                country_street_pools: parameters.country_street_pools,
+            });
+            if let Err(e) = options.and_then(read_and_compress) {
+                error!("{}", e)
             }
         }
-        Subcommand::Serve(parameters) => serve(parameters.world, parameters.ip, parameters.port),
-        Subcommand::Compress(_) => {
-            if let Err(e) = read_and_compress() {
+        Subcommand::Update(parameters) => {
+            if let Err(e) = update_store(parameters.store, parameters.changes, parameters.export) {
+                error!("{}", e)
+            }
+        }
+        Subcommand::Merge(parameters) => {
+            if let Err(e) = merge_worlds(parameters.worlds) {
+                error!("{}", e)
+            }
+        }
+        Subcommand::Diff(parameters) => {
+            if let Err(e) = diff_worlds(parameters.old, parameters.new, parameters.list) {
                 error!("{}", e)
             }
         }
@@ -0,0 +1,24 @@
+use std::{io, path::PathBuf};
+
+use log::info;
+
+use crate::{compress::World, serve::parse_into_world};
+
+pub fn merge_worlds(world_files: Vec<PathBuf>) -> Result<(), String> {
+    let mut worlds: Vec<World> = Vec::new();
+    for world_file in world_files {
+        let world = parse_into_world(world_file)?;
+        info!("World loaded, containing {} countries.", world.count());
+        worlds.push(world);
+    }
+    info!("Merging {} worlds...", worlds.len());
+    let world = World::merge(worlds);
+    info!(
+        "Done. Dumping world containing {} countries to stdout...",
+        world.count()
+    );
+    let stdout = io::stdout().lock();
+    world.write(stdout)?;
+    info!("Done!");
+    Ok(())
+}
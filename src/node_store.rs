@@ -0,0 +1,118 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use memmap2::MmapMut;
+use osmpbfreader::NodeId;
+
+/// Bytes per node: longitude and latitude, 4 bytes each.
+const ENTRY_SIZE: usize = 8;
+/// Added to each coordinate before storing, so no valid coordinate is stored as 0,
+/// which is what unwritten parts of the sparse file read as.
+const OFFSET: i64 = 2_000_000_000;
+const MIN_CAPACITY: u64 = 1 << 20;
+
+/// Node locations in a sparse, memory-mapped file, indexed by node id.
+/// Memory usage is left to the page cache of the operating system,
+/// the file is removed once dropped.
+pub struct NodeLocations {
+    path: PathBuf,
+    file: File,
+    mmap: MmapMut,
+    capacity: u64,
+}
+
+fn encode(v: i32) -> u32 {
+    (v as i64 + OFFSET) as u32
+}
+
+fn decode(v: u32) -> i32 {
+    (v as i64 - OFFSET) as i32
+}
+
+impl NodeLocations {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("Could not create node store {:?}: {}", path, e))?;
+        let mmap = Self::map(&file, MIN_CAPACITY)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            mmap,
+            capacity: MIN_CAPACITY,
+        })
+    }
+
+    fn map(file: &File, capacity: u64) -> Result<MmapMut, String> {
+        file.set_len(capacity * ENTRY_SIZE as u64)
+            .map_err(|e| format!("Could not grow node store: {}", e))?;
+        // Safety: the file is created and exclusively used by this struct,
+        // no one else is expected to modify it while mapped.
+        unsafe { MmapMut::map_mut(file) }.map_err(|e| format!("Could not map node store: {}", e))
+    }
+
+    pub fn set(&mut self, id: NodeId, decimicro_lon: i32, decimicro_lat: i32) -> Result<(), String> {
+        if id.0 < 0 {
+            return Err(format!("Node id {} is negative", id.0));
+        }
+        let index = id.0 as u64;
+        if index >= self.capacity {
+            let capacity = (index + 1).next_power_of_two();
+            self.mmap = Self::map(&self.file, capacity)?;
+            self.capacity = capacity;
+        }
+        let start = index as usize * ENTRY_SIZE;
+        self.mmap[start..start + 4].copy_from_slice(&encode(decimicro_lon).to_le_bytes());
+        self.mmap[start + 4..start + 8].copy_from_slice(&encode(decimicro_lat).to_le_bytes());
+        Ok(())
+    }
+
+    /// Location as (longitude, latitude) in decimicro degrees.
+    pub fn get(&self, id: NodeId) -> Option<(i32, i32)> {
+        if id.0 < 0 || id.0 as u64 >= self.capacity {
+            return None;
+        }
+        let start = id.0 as usize * ENTRY_SIZE;
+        let lon = u32::from_le_bytes(self.mmap[start..start + 4].try_into().unwrap());
+        let lat = u32::from_le_bytes(self.mmap[start + 4..start + 8].try_into().unwrap());
+        match lon {
+            0 => None,
+            _ => Some((decode(lon), decode(lat))),
+        }
+    }
+}
+
+impl Drop for NodeLocations {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_get_grow() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nodes.bin");
+        let mut nodes = NodeLocations::create(&path).unwrap();
+        nodes.set(NodeId(1), 0, 0).unwrap();
+        nodes.set(NodeId(7), -1_800_000_000, 900_000_000).unwrap();
+        nodes.set(NodeId(5_000_000), 133_800_000, -525_300_000).unwrap();
+        assert_eq!(nodes.get(NodeId(1)), Some((0, 0)));
+        assert_eq!(nodes.get(NodeId(7)), Some((-1_800_000_000, 900_000_000)));
+        assert_eq!(nodes.get(NodeId(5_000_000)), Some((133_800_000, -525_300_000)));
+        assert_eq!(nodes.get(NodeId(2)), None);
+        assert_eq!(nodes.get(NodeId(10_000_000_000)), None);
+        assert!(nodes.set(NodeId(-3), 0, 0).is_err());
+        drop(nodes);
+        assert!(!path.exists());
+    }
+}
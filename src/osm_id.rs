@@ -0,0 +1,76 @@
+//! Compact text representation of OSM ids, e.g. `n123`, `w456` or `r789`,
+//! used wherever ids are written as json.
+use osmpbfreader::{NodeId, OsmId, RelationId, WayId};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+pub fn to_string(id: &OsmId) -> String {
+    match id {
+        OsmId::Node(n) => format!("n{}", n.0),
+        OsmId::Way(w) => format!("w{}", w.0),
+        OsmId::Relation(r) => format!("r{}", r.0),
+    }
+}
+
+pub fn from_str(s: &str) -> Result<OsmId, String> {
+    let invalid = || format!("Invalid OSM id \"{}\"", s);
+    let (kind, id) = match (s.get(..1), s.get(1..)) {
+        (Some(kind), Some(id)) => (kind, id),
+        _ => return Err(invalid()),
+    };
+    let id: i64 = id.parse().map_err(|_| invalid())?;
+    match kind {
+        "n" => Ok(OsmId::Node(NodeId(id))),
+        "w" => Ok(OsmId::Way(WayId(id))),
+        "r" => Ok(OsmId::Relation(RelationId(id))),
+        _ => Err(invalid()),
+    }
+}
+
+pub fn serialize<S: Serializer>(id: &OsmId, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_string(id))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OsmId, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    from_str(&s).map_err(D::Error::custom)
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(id: &Option<OsmId>, serializer: S) -> Result<S::Ok, S::Error> {
+        match id {
+            Some(id) => serializer.serialize_some(&to_string(id)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<OsmId>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => from_str(&s).map(Some).map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for id in [
+            OsmId::Node(NodeId(123)),
+            OsmId::Way(WayId(4_567_890_123)),
+            OsmId::Relation(RelationId(7)),
+        ] {
+            assert_eq!(from_str(&to_string(&id)), Ok(id));
+        }
+        assert_eq!(to_string(&OsmId::Way(WayId(42))), "w42");
+        assert!(from_str("x12").is_err());
+        assert!(from_str("n").is_err());
+        assert!(from_str("").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::{BytesStart, Event};
+use serde::Serialize;
+
+/// Block of an osmChange file an object was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+fn attribute(e: &BytesStart, key: &str) -> Result<Option<String>, String> {
+    for attr in e.attributes() {
+        let attr = attr.map_err(|e| e.to_string())?;
+        if attr.key.as_ref() == key.as_bytes() {
+            let value = attr.unescape_value().map_err(|e| e.to_string())?;
+            return Ok(Some(value.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn required_attribute(e: &BytesStart, key: &str) -> Result<String, String> {
+    attribute(e, key)?.ok_or(format!(
+        "Element <{}> is missing attribute \"{}\"",
+        String::from_utf8_lossy(e.name().as_ref()),
+        key
+    ))
+}
+
+fn id_attribute(e: &BytesStart, key: &str) -> Result<i64, String> {
+    let id = required_attribute(e, key)?;
+    id.parse()
+        .map_err(|_| format!("Invalid id \"{}\" in attribute \"{}\"", id, key))
+}
+
+/// Degrees as in OSM XML to the decimicro degrees used by PBF.
+fn decimicro_attribute(e: &BytesStart, key: &str, action: Option<Action>) -> Result<i32, String> {
+    // Deleted nodes in osmChange files carry no location.
+    if action == Some(Action::Delete) && attribute(e, key)?.is_none() {
+        return Ok(0);
+    }
+    let degrees = required_attribute(e, key)?;
+    degrees
+        .parse::<f64>()
+        .map(|d| (d * 10_000_000.).round() as i32)
+        .map_err(|_| format!("Invalid coordinate \"{}\" in attribute \"{}\"", degrees, key))
+}
+
+fn start_object(e: &BytesStart, action: Option<Action>) -> Result<Option<OsmObj>, String> {
+    let obj = match e.name().as_ref() {
+        b"node" => OsmObj::Node(Node {
+            id: NodeId(id_attribute(e, "id")?),
+            tags: Tags::new(),
+            decimicro_lat: decimicro_attribute(e, "lat", action)?,
+            decimicro_lon: decimicro_attribute(e, "lon", action)?,
+        }),
+        b"way" => OsmObj::Way(Way {
+            id: WayId(id_attribute(e, "id")?),
+            tags: Tags::new(),
+            nodes: Vec::new(),
+        }),
+        b"relation" => OsmObj::Relation(Relation {
+            id: RelationId(id_attribute(e, "id")?),
+            tags: Tags::new(),
+            refs: Vec::new(),
+        }),
+        _ => return Ok(None),
+    };
+    Ok(Some(obj))
+}
+
+fn add_child(obj: &mut OsmObj, e: &BytesStart) -> Result<(), String> {
+    match (e.name().as_ref(), obj) {
+        (b"tag", OsmObj::Node(Node { tags, .. }))
+        | (b"tag", OsmObj::Way(Way { tags, .. }))
+        | (b"tag", OsmObj::Relation(Relation { tags, .. })) => {
+            let key = required_attribute(e, "k")?;
+            let value = required_attribute(e, "v")?;
+            tags.insert(key.into(), value.into());
+        }
+        (b"nd", OsmObj::Way(way)) => way.nodes.push(NodeId(id_attribute(e, "ref")?)),
+        (b"member", OsmObj::Relation(relation)) => {
+            let id = id_attribute(e, "ref")?;
+            let member = match required_attribute(e, "type")?.as_str() {
+                "node" => OsmId::Node(NodeId(id)),
+                "way" => OsmId::Way(WayId(id)),
+                "relation" => OsmId::Relation(RelationId(id)),
+                other => return Err(format!("Unknown member type \"{}\"", other)),
+            };
+            let role = attribute(e, "role")?.unwrap_or_default();
+            relation.refs.push(Ref {
+                member,
+                role: role.into(),
+            });
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn start_action(e: &BytesStart) -> Option<Action> {
+    match e.name().as_ref() {
+        b"create" => Some(Action::Create),
+        b"modify" => Some(Action::Modify),
+        b"delete" => Some(Action::Delete),
+        _ => None,
+    }
+}
+
+/// Read nodes, ways and relations from OSM XML.
+/// Objects inside `<create>`, `<modify>` or `<delete>` blocks of an
+/// osmChange document are passed along with their action.
+pub fn read_xml(
+    reader: impl BufRead,
+    mut f: impl FnMut(Option<Action>, OsmObj) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut reader = quick_xml::Reader::from_reader(reader);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut action: Option<Action> = None;
+    let mut current: Option<OsmObj> = None;
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| format!("Error at position {}: {}", reader.buffer_position(), e))?;
+        match event {
+            Event::Eof => break,
+            Event::Start(e) => match &mut current {
+                Some(obj) => add_child(obj, &e)?,
+                None => {
+                    if let Some(a) = start_action(&e) {
+                        action = Some(a);
+                    }
+                    current = start_object(&e, action)?;
+                }
+            },
+            Event::Empty(e) => match &mut current {
+                Some(obj) => add_child(obj, &e)?,
+                None => {
+                    if let Some(obj) = start_object(&e, action)? {
+                        f(action, obj)?;
+                    }
+                }
+            },
+            Event::End(e) => match e.name().as_ref() {
+                b"node" | b"way" | b"relation" => {
+                    if let Some(obj) = current.take() {
+                        f(action, obj)?;
+                    }
+                }
+                b"create" | b"modify" | b"delete" => action = None,
+                _ => (),
+            },
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// Read an osmChange document, as published by the OSM replication service.
+pub fn read_osm_change(reader: impl BufRead) -> Result<Vec<(Action, OsmObj)>, String> {
+    let mut changes = Vec::new();
+    read_xml(reader, |action, obj| match action {
+        Some(action) => {
+            changes.push((action, obj));
+            Ok(())
+        }
+        None => Err(format!(
+            "Object {:?} is not inside a create, modify or delete block",
+            obj.id()
+        )),
+    })?;
+    Ok(changes)
+}
+
+/// Open a file, decompressing it on the fly if it ends with `.gz` or `.bz2`.
+pub fn open_maybe_compressed(path: &Path) -> Result<Box<dyn BufRead>, String> {
+    let file = OpenOptions::new()
+        .read(true)
+        .create(false)
+        .open(path)
+        .map_err(|e| format!("Could not open {:?}: {}", path, e))?;
+    let reader: Box<dyn Read> = match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => Box::new(MultiGzDecoder::new(file)),
+        Some("bz2") => Box::new(MultiBzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::parse_coordinates::{collect_dependencies, is_address, obj_to_address};
+
+    #[test]
+    fn read_change_fixture() {
+        let changes = read_osm_change(include_str!("../tests/fixtures/change.osc").as_bytes())
+            .unwrap();
+        let summary: Vec<(Action, OsmId)> =
+            changes.iter().map(|(a, obj)| (*a, obj.id())).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Action::Create, OsmId::Node(NodeId(10))),
+                (Action::Modify, OsmId::Node(NodeId(1))),
+                (Action::Modify, OsmId::Node(NodeId(3))),
+                (Action::Delete, OsmId::Way(WayId(101))),
+            ]
+        );
+        let created = changes[0].1.node().unwrap();
+        assert_eq!(created.decimicro_lat, 525_200_000);
+        assert_eq!(created.decimicro_lon, 134_049_000);
+        assert_eq!(
+            created.tags.get("addr:street").map(|s| s.as_str()),
+            Some("Unter den Linden")
+        );
+    }
+
+    #[test]
+    fn deleted_nodes_without_location() {
+        let change = |action: &str| {
+            let xml = format!(
+                r#"<osmChange version="0.6"><{0}><node id="1" version="2"/></{0}></osmChange>"#,
+                action
+            );
+            read_osm_change(xml.as_bytes())
+        };
+        let deleted = change("delete").unwrap();
+        assert_eq!(deleted[0].1.node().unwrap().decimicro_lat, 0);
+        assert_eq!(
+            change("modify").err(),
+            Some("Element <node> is missing attribute \"lat\"".to_string())
+        );
+    }
+
+    #[test]
+    fn addresses_from_osm_fixture() {
+        let mut tree: BTreeMap<OsmId, OsmObj> = BTreeMap::new();
+        read_xml(include_str!("../tests/fixtures/area.osm").as_bytes(), |action, obj| {
+            assert_eq!(action, None);
+            tree.insert(obj.id(), obj);
+            Ok(())
+        })
+        .unwrap();
+        let addresses: Vec<(String, String, i32, i32)> = tree
+            .values()
+            .filter(|obj| is_address(obj.tags()))
+            .map(|obj| obj_to_address(obj.clone(), &collect_dependencies(obj, &tree)).unwrap())
+            .map(|a| (a.street.to_string(), a.housenumber.to_string(), a.long, a.lat))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("Invalidenstraße".into(), "1".into(), 133_800_000, 525_300_000),
+                ("Chausseestraße".into(), "12".into(), 133_850_000, 525_300_000),
+                ("Torstraße".into(), "5".into(), 134_000_000, 525_250_000),
+            ]
+        );
+    }
+}
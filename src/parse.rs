@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     fs::OpenOptions,
     io::{self, Read, Write},
     path::PathBuf,
@@ -7,27 +8,70 @@ use std::{
 };
 
 use log::info;
-use osmpbfreader::{OsmObj, Tags};
+use osmpbfreader::{OsmId, OsmObj, Tags};
 use serde::{Deserialize, Serialize};
 
 use crate::autofix::is_unfixable;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Address {
+    #[serde(
+        default,
+        with = "crate::osm_id::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<OsmId>,
     pub country: String,
     pub city: String,
+    /// Suburb or district within the city.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<String>,
+    #[serde(alias = "zip")]
     pub postcode: String,
     pub street: String,
     pub housenumber: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Street names in other languages, keyed by language code.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub street_names: BTreeMap<String, String>,
+    /// City names in other languages, keyed by language code.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub city_names: BTreeMap<String, String>,
+    /// Location in decimicro degrees, as computed by the parse stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lat: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct IncompleteAddress {
+    #[serde(
+        default,
+        with = "crate::osm_id::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<OsmId>,
     pub country: Option<String>,
     pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<String>,
+    #[serde(alias = "zip")]
     pub postcode: Option<String>,
     pub street: Option<String>,
     pub housenumber: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub street_names: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub city_names: BTreeMap<String, String>,
+    /// Location in decimicro degrees, as computed by the parse stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lat: Option<i32>,
 }
 
 impl IncompleteAddress {
@@ -40,11 +84,18 @@ impl IncompleteAddress {
     }
     pub fn into_complete(self) -> Option<Address> {
         Some(Address {
+            id: self.id,
             country: self.country?,
             city: self.city?,
+            district: self.district,
             postcode: self.postcode?,
             street: self.street?,
             housenumber: self.housenumber?,
+            unit: self.unit,
+            street_names: self.street_names,
+            city_names: self.city_names,
+            long: self.long,
+            lat: self.lat,
         })
     }
 }
@@ -105,7 +156,7 @@ fn nice_print(addresses: usize, entities: usize, bytes: usize, start: &Instant)
     );
 }
 
-fn process_tags(tags: Tags) -> Option<String> {
+fn process_tags(id: OsmId, tags: Tags) -> Option<String> {
     if tags.is_empty() {
         return None;
     }
@@ -119,11 +170,18 @@ fn process_tags(tags: Tags) -> Option<String> {
         (None, None, None, None, None) => None,
         (co, ci, po, st, hn) => {
             let address = IncompleteAddress {
+                id: Some(id),
                 housenumber: hn.map(|s| s.to_string()),
                 postcode: po.map(|s| s.to_string()),
                 city: ci.map(|s| s.to_string()),
+                district: None,
                 street: st.map(|s| s.to_string()),
                 country: co.map(|s| s.to_string()),
+                unit: None,
+                street_names: BTreeMap::new(),
+                city_names: BTreeMap::new(),
+                long: None,
+                lat: None,
             };
             if is_unfixable(&address) {
                 None
@@ -150,24 +208,16 @@ pub fn stdin_stdout_database(input: PathBuf) -> Result<(), String> {
     for obj in pbf.par_iter() {
         let obj = obj.map_err(|e| format!("{:?}", e))?;
 
-        let way_node_rel = match &obj {
-            &OsmObj::Way(_) => "way ",
-            &OsmObj::Relation(_) => "rel ",
-            &OsmObj::Node(_) => "nod ",
-        };
-
+        let id = obj.id();
         let tags = match obj {
             OsmObj::Way(w) => w.tags,
             OsmObj::Node(n) => n.tags,
             OsmObj::Relation(r) => r.tags,
         };
         counter_entities += 1;
-        match process_tags(tags) {
+        match process_tags(id, tags) {
             None => (),
             Some(t) => {
-                stdout
-                    .write_all(way_node_rel.as_bytes())
-                    .expect("Write stdout");
                 stdout
                     .write_all(t.as_bytes())
                     .expect("Error writing to stdout");
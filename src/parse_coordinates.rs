@@ -3,8 +3,8 @@ use std::{
     cmp::max,
     collections::{BTreeMap, HashMap, HashSet},
     fs::{File, OpenOptions},
-    io::{self, BufReader, Read, Seek, Write},
-    path::PathBuf,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write},
+    path::{Path, PathBuf},
     rc::Rc,
     sync::{
         mpsc::{channel, Receiver},
@@ -15,42 +15,94 @@ use std::{
     vec,
 };
 
-use log::{error, info};
+use log::{error, info, warn};
 use num_format::{Locale, ToFormattedString};
 use osmpbfreader::{
     reader::ObjAndDeps, Node, NodeId, OsmId, OsmObj, OsmPbfReader, Relation, Tags, Way,
 };
 use serde::{Deserialize, Serialize};
 use smartstring::{LazyCompact, SmartString};
+use tempfile::NamedTempFile;
 
-use crate::verbose_reader::VerboseReader;
+use crate::{
+    address_store::AddressStore,
+    node_store::NodeLocations,
+    osm_xml::read_xml,
+    region::{BoundingBox, Polygon},
+    verbose_reader::VerboseReader,
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// Protocolbuffer Binary Format, *.osm.pbf
+    Pbf,
+    /// OSM XML, *.osm, *.osm.bz2, *.osm.gz
+    Xml,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+impl InputFormat {
+    pub fn from_path(path: &Path) -> Self {
+        let name = path.to_string_lossy().to_lowercase();
+        if [".osm", ".osm.bz2", ".osm.gz", ".xml", ".xml.bz2", ".xml.gz"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+        {
+            InputFormat::Xml
+        } else {
+            InputFormat::Pbf
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IncompleteAddressCoord {
+    #[serde(with = "crate::osm_id")]
+    pub id: OsmId,
     pub country: Option<SmartString<LazyCompact>>,
     pub city: Option<SmartString<LazyCompact>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub district: Option<SmartString<LazyCompact>>,
     pub zip: Option<SmartString<LazyCompact>>,
     pub street: SmartString<LazyCompact>,
     pub housenumber: SmartString<LazyCompact>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<SmartString<LazyCompact>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub floor: Option<SmartString<LazyCompact>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub street_names: BTreeMap<String, SmartString<LazyCompact>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub city_names: BTreeMap<String, SmartString<LazyCompact>>,
     pub long: i32,
     pub lat: i32,
 }
 
 impl IncompleteAddressCoord {
-    pub fn from_incomplete_address_and_coords(inc: IncompleteAddress, long: i32, lat: i32) -> Self {
+    pub fn from_incomplete_address_and_coords(
+        id: OsmId,
+        inc: IncompleteAddress,
+        long: i32,
+        lat: i32,
+    ) -> Self {
         Self {
+            id,
             country: inc.country,
             city: inc.city,
+            district: inc.district,
             housenumber: inc.housenumber,
             zip: inc.zip,
             street: inc.street,
+            unit: inc.unit,
+            floor: inc.floor,
+            street_names: inc.street_names,
+            city_names: inc.city_names,
             long,
             lat,
         }
     }
-    pub fn from_tags_and_coords(t: Tags, long: i32, lat: i32) -> Option<Self> {
+    pub fn from_tags_and_coords(id: OsmId, t: Tags, long: i32, lat: i32) -> Option<Self> {
         let inc = IncompleteAddress::from_tags(t)?;
-        Some(Self::from_incomplete_address_and_coords(inc, long, lat))
+        Some(Self::from_incomplete_address_and_coords(id, inc, long, lat))
     }
 }
 
@@ -58,9 +110,93 @@ impl IncompleteAddressCoord {
 pub struct IncompleteAddress {
     pub country: Option<SmartString<LazyCompact>>,
     pub city: Option<SmartString<LazyCompact>>,
+    pub district: Option<SmartString<LazyCompact>>,
     pub zip: Option<SmartString<LazyCompact>>,
     pub street: SmartString<LazyCompact>,
     pub housenumber: SmartString<LazyCompact>,
+    pub unit: Option<SmartString<LazyCompact>>,
+    pub floor: Option<SmartString<LazyCompact>>,
+    pub street_names: BTreeMap<String, SmartString<LazyCompact>>,
+    pub city_names: BTreeMap<String, SmartString<LazyCompact>>,
+}
+
+/// Values of `<key>:<lang>` tags, e.g. `addr:street:fr`, keyed by language code.
+fn language_variants(t: &Tags, key: &str) -> BTreeMap<String, SmartString<LazyCompact>> {
+    let is_language = |lang: &str| {
+        let mut parts = lang.splitn(2, '-');
+        let code = parts.next().unwrap_or_default();
+        (2..=3).contains(&code.len())
+            && code.chars().all(|c| c.is_ascii_lowercase())
+            && parts.all(|script| script.chars().all(|c| c.is_ascii_alphabetic()))
+    };
+    t.iter()
+        .filter_map(|(k, v)| {
+            let lang = k.strip_prefix(key)?.strip_prefix(':')?;
+            is_language(lang).then(|| (lang.to_string(), v.clone()))
+        })
+        .collect()
+}
+
+/// Values of `place` naming what addresses refer to with `addr:city`.
+const CITY_PLACES: [&str; 5] = ["city", "town", "village", "hamlet", "municipality"];
+
+type Variants = BTreeMap<String, SmartString<LazyCompact>>;
+
+/// Names in other languages of places and streets, from their `name:<lang>` tags,
+/// for addresses without `addr:city:<lang>` or `addr:street:<lang>` tags.
+/// Addresses only refer to them by name, so a name translated differently by several objects
+/// is ambiguous and not used.
+#[derive(Debug, Default)]
+pub struct NameVariants {
+    places: HashMap<SmartString<LazyCompact>, Option<Variants>>,
+    streets: HashMap<SmartString<LazyCompact>, Option<Variants>>,
+}
+
+impl NameVariants {
+    /// Remember the names of a place or street, other objects are ignored.
+    pub fn insert(&mut self, tags: &Tags) {
+        let is_city = |place: &SmartString<LazyCompact>| CITY_PLACES.contains(&place.as_str());
+        let table = if tags.get("place").is_some_and(is_city) {
+            &mut self.places
+        } else if tags.contains_key("highway") {
+            &mut self.streets
+        } else {
+            return;
+        };
+        let name = match tags.get("name") {
+            Some(name) => name,
+            None => return,
+        };
+        let variants = language_variants(tags, "name");
+        if variants.is_empty() {
+            return;
+        }
+        table
+            .entry(name.clone())
+            .and_modify(|known| {
+                if known.as_ref() != Some(&variants) {
+                    *known = None;
+                }
+            })
+            .or_insert(Some(variants));
+    }
+    /// Add the names of its city and street, unless the address has names in other languages.
+    fn complete(&self, address: &mut IncompleteAddressCoord) {
+        if address.street_names.is_empty() {
+            if let Some(Some(variants)) = self.streets.get(&address.street) {
+                address.street_names = variants.clone();
+            }
+        }
+        if address.city_names.is_empty() {
+            let variants = address.city.as_ref().and_then(|city| self.places.get(city));
+            if let Some(Some(variants)) = variants {
+                address.city_names = variants.clone();
+            }
+        }
+    }
+    fn len(&self) -> usize {
+        self.places.len() + self.streets.len()
+    }
 }
 
 impl IncompleteAddress {
@@ -68,9 +204,21 @@ impl IncompleteAddress {
         Some(Self {
             country: t.get("addr:country").cloned(),
             city: t.get("addr:city").cloned(),
+            district: t
+                .get("addr:suburb")
+                .or_else(|| t.get("addr:district"))
+                .cloned(),
             zip: t.get("addr:postcode").cloned(),
             street: t.get("addr:street").cloned()?,
             housenumber: t.get("addr:housenumber").cloned()?,
+            // Tagging differs by region, all of them name the apartment within the building.
+            unit: ["addr:unit", "addr:flat", "addr:door"]
+                .iter()
+                .find_map(|key| t.get(*key))
+                .cloned(),
+            floor: t.get("addr:floor").cloned(),
+            street_names: language_variants(&t, "addr:street"),
+            city_names: language_variants(&t, "addr:city"),
         })
     }
 }
@@ -85,7 +233,8 @@ fn vec_to_btree(v: &Vec<Rc<ObjAndDeps>>) -> BTreeMap<OsmId, OsmObj> {
     t
 }
 
-fn avg_coords(it: impl Iterator<Item = (i32, i32)>) -> (i32, i32) {
+/// Average of the coordinates, `None` if there are none.
+fn avg_coords(it: impl Iterator<Item = (i32, i32)>) -> Option<(i32, i32)> {
     let (mut a, mut b) = (0, 0);
     let mut count = 0;
     for (aa, bb) in it {
@@ -93,7 +242,10 @@ fn avg_coords(it: impl Iterator<Item = (i32, i32)>) -> (i32, i32) {
         a += aa as i64;
         b += bb as i64;
     }
-    ((a / count) as i32, (b / count) as i32)
+    match count {
+        0 => None,
+        _ => Some(((a / count) as i32, (b / count) as i32)),
+    }
 }
 
 struct CountingReader<R: Read> {
@@ -152,20 +304,20 @@ impl<R: Read> CountingReader<R> {
     }
 }
 
-fn is_address(t: &Tags) -> bool {
+pub fn is_address(t: &Tags) -> bool {
     t.get("addr:housenumber").is_some() && t.get("addr:street").is_some()
 }
 
 fn node_to_address(
     Node {
-        id: _,
+        id,
         tags,
         decimicro_lat,
         decimicro_lon,
     }: Node,
 ) -> Result<IncompleteAddressCoord, String> {
-    IncompleteAddressCoord::from_tags_and_coords(tags, decimicro_lon, decimicro_lat)
-        .ok_or(format!("TODO"))
+    IncompleteAddressCoord::from_tags_and_coords(OsmId::Node(id), tags, decimicro_lon, decimicro_lat)
+        .ok_or(format!("Node {} is missing housenumber or street.", id.0))
 }
 
 fn way_to_cordinates(
@@ -174,9 +326,12 @@ fn way_to_cordinates(
 ) -> Result<(i32, i32), String> {
     let coordinates: Result<Vec<(i32, i32)>, String> = nodes
         .iter()
-        .map(|node_id| tree.get(&OsmId::Node(node_id.clone())))
-        .map(|node| match node {
-            None => Err(format!("Way {} references to not existing node id.", id.0)),
+        .map(|node_id| (node_id, tree.get(&OsmId::Node(node_id.clone()))))
+        .map(|(node_id, node)| match node {
+            None => Err(format!(
+                "Way {} references to not existing node {}.",
+                id.0, node_id.0
+            )),
             Some(OsmObj::Way(w)) => Err(format!(
                 "Way {} references to way {}. Not a node.",
                 id.0, w.id.0
@@ -188,7 +343,7 @@ fn way_to_cordinates(
             Some(OsmObj::Node(n)) => Ok((n.decimicro_lon, n.decimicro_lat)),
         })
         .collect();
-    Ok(avg_coords(coordinates?.into_iter()))
+    avg_coords(coordinates?.into_iter()).ok_or(format!("Way {} has no nodes.", id.0))
 }
 
 fn way_to_address(
@@ -196,7 +351,7 @@ fn way_to_address(
     tree: &BTreeMap<OsmId, OsmObj>,
 ) -> Result<IncompleteAddressCoord, String> {
     let (long, lat) = way_to_cordinates(way.clone(), tree)?;
-    IncompleteAddressCoord::from_tags_and_coords(way.tags, long, lat)
+    IncompleteAddressCoord::from_tags_and_coords(OsmId::Way(way.id), way.tags, long, lat)
         .ok_or("Missing housenumber or street".into())
 }
 
@@ -204,17 +359,21 @@ fn relation_to_coordinates(
     relation: Relation,
     tree: &BTreeMap<OsmId, OsmObj>,
 ) -> Result<(i32, i32), String> {
+    let relation_id = relation.id;
     let result: Result<Vec<(i32, i32)>, String> = relation
         .refs
         .into_iter()
         .map(|id| match tree.get(&id.member) {
-            None => Err(format!("TODO")),
+            None => Err(format!(
+                "Relation {} references to not existing member {:?}.",
+                relation_id.0, id.member
+            )),
             Some(OsmObj::Node(node)) => Ok((node.decimicro_lon, node.decimicro_lat)),
             Some(OsmObj::Relation(rel)) => relation_to_coordinates(rel.clone(), tree),
             Some(OsmObj::Way(way)) => way_to_cordinates(way.clone(), tree),
         })
         .collect();
-    Ok(avg_coords(result?.into_iter()))
+    avg_coords(result?.into_iter()).ok_or(format!("Relation {} has no members.", relation_id.0))
 }
 
 fn relation_to_address(
@@ -222,10 +381,50 @@ fn relation_to_address(
     tree: &BTreeMap<OsmId, OsmObj>,
 ) -> Result<IncompleteAddressCoord, String> {
     let (long, lat) = relation_to_coordinates(relation.clone(), tree)?;
-    IncompleteAddressCoord::from_tags_and_coords(relation.tags, long, lat)
+    IncompleteAddressCoord::from_tags_and_coords(
+        OsmId::Relation(relation.id),
+        relation.tags,
+        long,
+        lat,
+    )
         .ok_or("Missing street or housenumber".into())
 }
 
+/// Compute the address of a node, way or relation.
+/// `tree` has to contain all objects the object depends on.
+pub fn obj_to_address(
+    obj: OsmObj,
+    tree: &BTreeMap<OsmId, OsmObj>,
+) -> Result<IncompleteAddressCoord, String> {
+    match obj {
+        OsmObj::Node(node) => node_to_address(node),
+        OsmObj::Way(way) => way_to_address(way, tree),
+        OsmObj::Relation(rel) => relation_to_address(rel, tree),
+    }
+}
+
+/// All objects an object depends on, recursively, as far as contained in `tree`.
+pub fn collect_dependencies(obj: &OsmObj, tree: &BTreeMap<OsmId, OsmObj>) -> BTreeMap<OsmId, OsmObj> {
+    let mut deps = BTreeMap::new();
+    let mut todo: Vec<OsmId> = Vec::new();
+    let push_refs = |obj: &OsmObj, todo: &mut Vec<OsmId>| match obj {
+        OsmObj::Node(_) => (),
+        OsmObj::Way(way) => todo.extend(way.nodes.iter().map(|n| OsmId::Node(*n))),
+        OsmObj::Relation(rel) => todo.extend(rel.refs.iter().map(|r| r.member)),
+    };
+    push_refs(obj, &mut todo);
+    while let Some(id) = todo.pop() {
+        if deps.contains_key(&id) {
+            continue;
+        }
+        if let Some(dep) = tree.get(&id) {
+            push_refs(dep, &mut todo);
+            deps.insert(id, dep.clone());
+        }
+    }
+    deps
+}
+
 fn reader_from_path_buf(path: PathBuf) -> Result<File, String> {
     OpenOptions::new()
         .read(true)
@@ -234,16 +433,76 @@ fn reader_from_path_buf(path: PathBuf) -> Result<File, String> {
         .map_err(|e| e.to_string())
 }
 
-fn output_items(elements: Receiver<(OsmObj, BTreeMap<OsmId, OsmObj>)>) -> Result<(), String> {
+/// Files written by the parse stage besides the addresses on stdout.
+#[derive(Debug, Default)]
+pub struct OutputOptions {
+    /// All addresses keyed by OSM id, see [AddressStore].
+    pub store: Option<PathBuf>,
+    /// Objects no address could be computed for, as json lines.
+    pub rejects: Option<PathBuf>,
+    /// Drop addresses outside of this bounding box.
+    pub bbox: Option<BoundingBox>,
+    /// Drop addresses outside of this polygon.
+    pub polygon: Option<Polygon>,
+    /// Collect the names of places and streets in other languages, see [NameVariants].
+    pub name_variants: bool,
+}
+
+impl OutputOptions {
+    fn in_region(&self, address: &IncompleteAddressCoord) -> bool {
+        let in_bbox = |bbox: &BoundingBox| bbox.contains(address.long, address.lat);
+        let in_polygon = |polygon: &Polygon| polygon.contains(address.long, address.lat);
+        self.bbox.as_ref().map_or(true, in_bbox) && self.polygon.as_ref().map_or(true, in_polygon)
+    }
+}
+
+/// An object no address could be computed for, e.g. a way referencing a node
+/// which is not contained in the extract.
+#[derive(Debug, Serialize)]
+struct Reject {
+    #[serde(with = "crate::osm_id")]
+    id: OsmId,
+    reason: String,
+}
+
+fn output_items(
+    elements: Receiver<(OsmObj, BTreeMap<OsmId, OsmObj>)>,
+    options: OutputOptions,
+    names: NameVariants,
+) -> Result<(), String> {
     let mut stdout = io::stdout().lock();
+    let mut store = options.store.as_ref().map(|_| AddressStore::default());
+    let mut rejects = match &options.rejects {
+        Some(path) => Some(BufWriter::new(
+            File::create(path)
+                .map_err(|e| format!("Could not create rejects file {:?}: {}", path, e))?,
+        )),
+        None => None,
+    };
     let mut count = 0;
+    let mut rejected = 0;
+    let mut outside = 0;
     while let Ok((obj, deps)) = elements.recv() {
-        let tags = obj.tags();
-        let addr = match obj {
-            OsmObj::Node(node) => node_to_address(node.clone())?,
-            OsmObj::Way(way) => way_to_address(way.clone(), &deps)?,
-            OsmObj::Relation(rel) => relation_to_address(rel.clone(), &deps)?,
+        let mut addr = match obj_to_address(obj.clone(), &deps) {
+            Ok(addr) => addr,
+            Err(reason) => {
+                warn!("Skipping {:?}: {}", obj.id(), reason);
+                rejected += 1;
+                if let Some(rejects) = &mut rejects {
+                    let reject = Reject { id: obj.id(), reason };
+                    serde_json::to_writer(&mut *rejects, &reject).map_err(|e| e.to_string())?;
+                    rejects
+                        .write_all("\n".as_bytes())
+                        .map_err(|e| e.to_string())?;
+                }
+                continue;
+            }
         };
+        if !options.in_region(&addr) {
+            outside += 1;
+            continue;
+        }
+        names.complete(&mut addr);
         serde_json::to_writer(&mut stdout, &addr).map_err(|e| e.to_string())?;
         stdout
             .write_all("\n".as_bytes())
@@ -252,11 +511,230 @@ fn output_items(elements: Receiver<(OsmObj, BTreeMap<OsmId, OsmObj>)>) -> Result
             info!("{}K addresses processed", count / 1000);
         }
         count += 1;
+        if let Some(store) = &mut store {
+            store.insert(obj, deps, addr);
+        }
+    }
+    info!(
+        "{} addresses written, {} outside of the region, {} objects rejected.",
+        count, outside, rejected
+    );
+    if let Some(rejects) = &mut rejects {
+        rejects.flush().map_err(|e| e.to_string())?;
+    }
+    if let (Some(store), Some(path)) = (store, options.store) {
+        info!("Writing address store to {:?}...", path);
+        store.save(&path)?;
     }
     Ok(())
 }
 
-pub fn process_osm_pdf_to_stdout(input: PathBuf, memory_gib: f32) -> Result<(), String> {
+/// OSM XML files are expected to be small, like exports of JOSM or Overpass.
+/// Therefore everything is read into memory at once.
+pub fn process_osm_xml_to_stdout(
+    reader: impl BufRead,
+    output: OutputOptions,
+) -> Result<(), String> {
+    let mut tree: BTreeMap<OsmId, OsmObj> = BTreeMap::new();
+    read_xml(reader, |_action, obj| {
+        tree.insert(obj.id(), obj);
+        Ok(())
+    })?;
+    info!("Read {} objects.", tree.len());
+    let mut names = NameVariants::default();
+    if output.name_variants {
+        for obj in tree.values() {
+            names.insert(obj.tags());
+        }
+    }
+
+    let (sender, recv) = channel();
+    let output_thread = spawn(|| output_items(recv, output, names));
+    for obj in tree.values().filter(|obj| is_address(obj.tags())) {
+        let deps = collect_dependencies(obj, &tree);
+        sender
+            .send((obj.clone(), deps))
+            .map_err(|_| "Output thread terminated early".to_string())?;
+    }
+    drop(sender);
+    output_thread
+        .join()
+        .map_err(|_| "Error joining thread".to_string())?
+}
+
+/// Names in other languages of places and streets, read in a pass of their own,
+/// as addresses may come before the place or street they refer to.
+/// Nothing is read unless asked for with `--name-variants`.
+fn read_name_variants<R: Read + Seek>(
+    pbf: &mut OsmPbfReader<R>,
+    output: &OutputOptions,
+) -> Result<NameVariants, String> {
+    if !output.name_variants {
+        return Ok(NameVariants::default());
+    }
+    info!("Read names of places and streets in other languages...");
+    let mut names = NameVariants::default();
+    for obj in pbf.par_iter() {
+        let obj = obj.map_err(|e| format!("{:?}", e))?;
+        names.insert(obj.tags());
+    }
+    pbf.rewind().map_err(|e| format!("{:?}", e))?;
+    info!("Found {} names of places and streets.", names.len());
+    Ok(names)
+}
+
+/// Node without tags, with the location taken from the node store.
+fn stored_node(id: NodeId, nodes: &NodeLocations) -> Option<(OsmId, OsmObj)> {
+    let (lon, lat) = nodes.get(id)?;
+    let node = Node {
+        id,
+        tags: Tags::new(),
+        decimicro_lat: lat,
+        decimicro_lon: lon,
+    };
+    Some((OsmId::Node(id), OsmObj::Node(node)))
+}
+
+/// Nodes directly referenced by a way or relation.
+fn stored_nodes(obj: &OsmObj, nodes: &NodeLocations) -> BTreeMap<OsmId, OsmObj> {
+    match obj {
+        OsmObj::Node(_) => BTreeMap::new(),
+        OsmObj::Way(way) => way.nodes.iter().filter_map(|id| stored_node(*id, nodes)).collect(),
+        OsmObj::Relation(rel) => rel
+            .refs
+            .iter()
+            .filter_map(|r| r.member.node())
+            .filter_map(|id| stored_node(id, nodes))
+            .collect(),
+    }
+}
+
+/// Members of the relations in `tree`, recursively, which are neither in `tree` nor nodes.
+fn missing_members(tree: &BTreeMap<OsmId, OsmObj>) -> HashSet<OsmId> {
+    tree.values()
+        .filter_map(|obj| obj.relation())
+        .flat_map(|rel| rel.refs.iter().map(|r| r.member))
+        .filter(|id| !id.is_node() && !tree.contains_key(id))
+        .collect()
+}
+
+/// Alternative to `process_osm_pdf_to_stdout` with predictable memory usage.
+/// The first pass writes the location of every node into a memory-mapped file
+/// indexed by node id and resolves nodes and ways, which are sorted after all nodes.
+/// Further passes only collect the ways and relations referenced by relations.
+pub fn process_osm_pbf_with_node_store_to_stdout(
+    input: PathBuf,
+    node_store: PathBuf,
+    output: OutputOptions,
+) -> Result<(), String> {
+    let file = reader_from_path_buf(input.clone())?;
+    let meta = file
+        .metadata()
+        .map_err(|e| format!("Could not read file size: {}", e))?;
+    let (reader, reader_manager) = VerboseReader::new(file, meta.len() as usize);
+    let mut pbf = osmpbfreader::OsmPbfReader::new(reader);
+    let mut nodes = NodeLocations::create(&node_store)?;
+    info!(
+        "Read file \"{}\", storing node locations in {:?}...",
+        input.as_os_str().to_str().unwrap_or("<invalid path>"),
+        node_store
+    );
+    let reader_manager = reader_manager.print_interval(Duration::from_secs(3));
+    reader_manager.start_printing();
+    let names = read_name_variants(&mut pbf, &output)?;
+    let (sender, recv) = channel();
+    let output_thread = spawn(|| output_items(recv, output, names));
+    let send = |obj: OsmObj, deps: BTreeMap<OsmId, OsmObj>| {
+        sender
+            .send((obj, deps))
+            .map_err(|_| "Output thread terminated early".to_string())
+    };
+
+    // Address relations and everything they reference, besides nodes.
+    let mut tree: BTreeMap<OsmId, OsmObj> = BTreeMap::new();
+    for obj in pbf.par_iter() {
+        let obj = obj.map_err(|e| format!("{:?}", e))?;
+        match obj {
+            OsmObj::Node(node) => {
+                nodes.set(node.id, node.decimicro_lon, node.decimicro_lat)?;
+                if is_address(&node.tags) {
+                    send(OsmObj::Node(node), BTreeMap::new())?;
+                }
+            }
+            OsmObj::Way(way) => {
+                let way = OsmObj::Way(way);
+                if is_address(way.tags()) {
+                    let deps = stored_nodes(&way, &nodes);
+                    send(way, deps)?;
+                }
+            }
+            OsmObj::Relation(rel) => {
+                if is_address(&rel.tags) {
+                    tree.insert(OsmId::Relation(rel.id), OsmObj::Relation(rel));
+                }
+            }
+        }
+    }
+    let relations: Vec<OsmId> = tree.keys().cloned().collect();
+
+    loop {
+        let missing = missing_members(&tree);
+        if missing.is_empty() {
+            break;
+        }
+        info!("Read again to collect {} members of relations...", missing.len());
+        pbf.rewind().map_err(|e| format!("{:?}", e))?;
+        let before = tree.len();
+        for obj in pbf.par_iter() {
+            let obj = obj.map_err(|e| format!("{:?}", e))?;
+            if missing.contains(&obj.id()) {
+                tree.insert(obj.id(), obj);
+            }
+        }
+        if tree.len() == before {
+            // Referenced objects are not contained in the file, e.g. cut off by an extract.
+            break;
+        }
+    }
+    reader_manager.stop_printing();
+
+    for id in relations {
+        let obj = tree[&id].clone();
+        let mut deps = collect_dependencies(&obj, &tree);
+        let mut node_deps = stored_nodes(&obj, &nodes);
+        for dep in deps.values() {
+            node_deps.append(&mut stored_nodes(dep, &nodes));
+        }
+        deps.append(&mut node_deps);
+        send(obj, deps)?;
+    }
+
+    info!("Join stdout thread...");
+    drop(sender);
+    output_thread
+        .join()
+        .map_err(|_| "Error joining thread".to_string())?
+}
+
+/// Copy stdin into a temporary file, which is deleted once dropped.
+pub fn spool_stdin(dir: Option<PathBuf>) -> Result<NamedTempFile, String> {
+    let mut file = match dir {
+        Some(dir) => NamedTempFile::new_in(dir),
+        None => NamedTempFile::new(),
+    }
+    .map_err(|e| format!("Could not create temporary file: {}", e))?;
+    info!("Buffering stdin in {:?}...", file.path());
+    let bytes = io::copy(&mut io::stdin().lock(), &mut file)
+        .map_err(|e| format!("Could not buffer stdin: {}", e))?;
+    info!("Buffered {} of stdin.", human_bytes::human_bytes(bytes as f64));
+    Ok(file)
+}
+
+pub fn process_osm_pdf_to_stdout(
+    input: PathBuf,
+    memory_gib: f32,
+    output: OutputOptions,
+) -> Result<(), String> {
     let memory_gib = if memory_gib < 0.1 {
         memory_gib 
     } else {
@@ -277,13 +755,20 @@ pub fn process_osm_pdf_to_stdout(input: PathBuf, memory_gib: f32) -> Result<(),
     info!("This might take multiple minutes...");
     let reader_manager = reader_manager.print_interval(Duration::from_secs(3));
     reader_manager.start_printing();
+    let names = read_name_variants(&mut pbf, &output)?;
     let (sender, recv) = channel();
 
-    let output_thread = spawn(|| output_items(recv));
+    let output_thread = spawn(|| output_items(recv, output, names));
 
+    // Once the output thread terminated, its error is returned after the join.
+    let mut closed = false;
     pbf.get_objs_and_deps_on_the_fly(
         |obj| is_address(obj.tags()),
-        |item| sender.send((item.inner, vec_to_btree(&item.deps))).unwrap(),
+        |item| {
+            if !closed && sender.send((item.inner, vec_to_btree(&item.deps))).is_err() {
+                closed = true;
+            }
+        },
         // 12 GiB for 6M objects
         // 2 GiB for 1M objects
         // 1 GiB for 500K objects
@@ -293,8 +778,154 @@ pub fn process_osm_pdf_to_stdout(input: PathBuf, memory_gib: f32) -> Result<(),
 
     info!("Join stdout thread...");
     drop(sender);
-    let _ = output_thread.join().map_err(|_| "Error joining thread".to_string())?;
+    output_thread
+        .join()
+        .map_err(|_| "Error joining thread".to_string())??;
     info!("Joined.");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use osmpbfreader::WayId;
+
+    use super::*;
+
+    #[test]
+    fn broken_references_are_errors() {
+        let mut tags = Tags::new();
+        tags.insert("addr:street".into(), "Torstraße".into());
+        tags.insert("addr:housenumber".into(), "5".into());
+        let way = |nodes: Vec<NodeId>| {
+            OsmObj::Way(Way {
+                id: WayId(7),
+                tags: tags.clone(),
+                nodes,
+            })
+        };
+        let node = OsmObj::Node(Node {
+            id: NodeId(1),
+            tags: Tags::new(),
+            decimicro_lat: 525_250_000,
+            decimicro_lon: 134_000_000,
+        });
+        let tree: BTreeMap<OsmId, OsmObj> = [(node.id(), node)].into_iter().collect();
+
+        let address = obj_to_address(way(vec![NodeId(1)]), &tree).unwrap();
+        assert_eq!((address.long, address.lat), (134_000_000, 525_250_000));
+        assert_eq!(
+            obj_to_address(way(vec![NodeId(1), NodeId(2)]), &tree),
+            Err("Way 7 references to not existing node 2.".to_string())
+        );
+        assert_eq!(
+            obj_to_address(way(vec![]), &tree),
+            Err("Way 7 has no nodes.".to_string())
+        );
+    }
+
+    #[test]
+    fn names_of_places_and_streets() {
+        let tags = |pairs: &[(&str, &str)]| -> Tags {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).into(), (*v).into()))
+                .collect()
+        };
+        let mut names = NameVariants::default();
+        names.insert(&tags(&[
+            ("place", "city"),
+            ("name", "Bozen"),
+            ("name:it", "Bolzano"),
+        ]));
+        names.insert(&tags(&[
+            ("highway", "residential"),
+            ("name", "Hauptstraße"),
+        ]));
+        names.insert(&tags(&[
+            ("highway", "residential"),
+            ("name", "Laubengasse"),
+            ("name:it", "Via dei Portici"),
+        ]));
+        // Differently translated, so it is unknown which one is meant.
+        for translation in ["Via Roma", "Via Milano"] {
+            names.insert(&tags(&[
+                ("highway", "residential"),
+                ("name", "Bahnhofstraße"),
+                ("name:it", translation),
+            ]));
+        }
+        names.insert(&tags(&[
+            ("shop", "bakery"),
+            ("name", "Rainer"),
+            ("name:it", "Rainer"),
+        ]));
+        assert_eq!(names.len(), 3);
+        let address = |street: &str| {
+            let mut address = IncompleteAddressCoord::from_tags_and_coords(
+                OsmId::Node(NodeId(1)),
+                tags(&[
+                    ("addr:city", "Bozen"),
+                    ("addr:street", street),
+                    ("addr:housenumber", "1"),
+                ]),
+                0,
+                0,
+            )
+            .unwrap();
+            names.complete(&mut address);
+            let names = |variants: &BTreeMap<String, SmartString<LazyCompact>>| {
+                variants
+                    .iter()
+                    .map(|(lang, name)| format!("{}:{}", lang, name))
+                    .collect::<Vec<String>>()
+            };
+            (names(&address.city_names), names(&address.street_names))
+        };
+        assert_eq!(
+            address("Laubengasse"),
+            (vec!["it:Bolzano".into()], vec!["it:Via dei Portici".into()])
+        );
+        assert_eq!(
+            address("Bahnhofstraße"),
+            (vec!["it:Bolzano".into()], vec![])
+        );
+    }
+
+    #[test]
+    fn street_names_by_language() {
+        let mut tags = Tags::new();
+        tags.insert("addr:street".into(), "Rue Neuve - Nieuwstraat".into());
+        tags.insert("addr:street:fr".into(), "Rue Neuve".into());
+        tags.insert("addr:street:nl".into(), "Nieuwstraat".into());
+        tags.insert("addr:street:zh-Hant".into(), "新街".into());
+        tags.insert("addr:street:source".into(), "survey".into());
+        let names: Vec<(String, String)> = language_variants(&tags, "addr:street")
+            .into_iter()
+            .map(|(lang, name)| (lang, name.to_string()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("fr".to_string(), "Rue Neuve".to_string()),
+                ("nl".to_string(), "Nieuwstraat".to_string()),
+                ("zh-Hant".to_string(), "新街".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn store_errors_are_returned() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = OutputOptions {
+            store: Some(dir.path().join("missing").join("addresses.store")),
+            ..Default::default()
+        };
+        let (sender, recv) = channel();
+        drop(sender);
+        let result = output_items(recv, output, NameVariants::default());
+        assert!(result
+            .unwrap_err()
+            .starts_with("Could not write address store"));
+    }
+}
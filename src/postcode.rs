@@ -0,0 +1,133 @@
+//! Per-country postal code formats, so `D-10115`, `10115 ` and `10115` end up in the same postal area.
+//! Pattern characters: `9` is a digit, `A` a letter, `X` either, space and `-` are separators.
+//! Separators are optional in the input, but always written in the canonical form.
+
+/// Country code, fixed prefix of the canonical form, and accepted formats.
+const FORMATS: &[(&str, &str, &[&str])] = &[
+    ("AT", "", &["9999"]),
+    ("BE", "", &["9999"]),
+    ("BG", "", &["9999"]),
+    ("CA", "", &["A9A 9A9"]),
+    ("CH", "", &["9999"]),
+    ("CY", "", &["9999"]),
+    ("CZ", "", &["999 99"]),
+    ("DE", "", &["99999"]),
+    ("DK", "", &["9999"]),
+    ("EE", "", &["99999"]),
+    ("ES", "", &["99999"]),
+    ("FI", "", &["99999"]),
+    ("FR", "", &["99999"]),
+    (
+        "GB",
+        "",
+        &[
+            "A9 9AA", "A99 9AA", "AA9 9AA", "AA99 9AA", "A9A 9AA", "AA9A 9AA",
+        ],
+    ),
+    ("GR", "", &["999 99"]),
+    ("HR", "", &["99999"]),
+    ("HU", "", &["9999"]),
+    ("IE", "", &["A99 XXXX", "A9A XXXX"]),
+    ("IT", "", &["99999"]),
+    ("JP", "", &["999-9999"]),
+    ("LT", "LT-", &["99999"]),
+    ("LU", "", &["9999"]),
+    ("LV", "LV-", &["9999"]),
+    ("MT", "", &["AAA 9999"]),
+    ("NL", "", &["9999 AA"]),
+    ("NO", "", &["9999"]),
+    ("PL", "", &["99-999"]),
+    ("PT", "", &["9999-999"]),
+    ("RO", "", &["999999"]),
+    ("SE", "", &["999 99"]),
+    ("SI", "", &["9999"]),
+    ("SK", "", &["999 99"]),
+    ("US", "", &["99999", "99999-9999"]),
+];
+
+fn is_separator(c: char) -> bool {
+    c == ' ' || c == '-'
+}
+
+fn matches(class: char, c: char) -> bool {
+    match class {
+        '9' => c.is_ascii_digit(),
+        'A' => c.is_ascii_uppercase(),
+        'X' => c.is_ascii_digit() || c.is_ascii_uppercase(),
+        _ => class == c,
+    }
+}
+
+/// The characters of `compact` laid out like `pattern`, if they match it.
+fn format_as(pattern: &str, compact: &[char]) -> Option<String> {
+    let classes: Vec<char> = pattern.chars().filter(|c| !is_separator(*c)).collect();
+    if classes.len() != compact.len() || !classes.iter().zip(compact).all(|(p, c)| matches(*p, *c))
+    {
+        return None;
+    }
+    let mut chars = compact.iter();
+    Some(
+        pattern
+            .chars()
+            .map(|p| match is_separator(p) {
+                true => p,
+                false => *chars.next().unwrap(),
+            })
+            .collect(),
+    )
+}
+
+/// Drop a leading country prefix like `D-`, `NL-` or `LV `, as long as the format starts with a digit.
+fn strip_country_prefix<'a>(code: &'a str, patterns: &[&str]) -> &'a str {
+    if !patterns.iter().all(|p| p.starts_with('9')) {
+        return code;
+    }
+    let letters = code.chars().take_while(|c| c.is_ascii_uppercase()).count();
+    match (1..=3).contains(&letters) {
+        true => code[letters..].trim_start_matches(is_separator),
+        false => code,
+    }
+}
+
+/// Canonical form of a postal code, or `None` if it does not match the format of the country.
+/// Codes of countries without a known format only have their whitespace cleaned up.
+pub fn normalize(country: &str, code: &str) -> Option<String> {
+    let code = code.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let (prefix, patterns) = match FORMATS.iter().find(|(c, _, _)| *c == country) {
+        Some((_, prefix, patterns)) => (prefix, patterns),
+        None => return (!code.is_empty()).then_some(code),
+    };
+    let upper = code.to_uppercase();
+    let compact: Vec<char> = strip_country_prefix(&upper, patterns)
+        .chars()
+        .filter(|c| !is_separator(*c))
+        .collect();
+    patterns
+        .iter()
+        .find_map(|pattern| format_as(pattern, &compact))
+        .map(|code| format!("{}{}", prefix, code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_postcodes() {
+        assert_eq!(normalize("DE", "10115 "), Some("10115".into()));
+        assert_eq!(normalize("DE", "D-10115"), Some("10115".into()));
+        assert_eq!(normalize("DE", "1011"), None);
+        assert_eq!(normalize("GB", "sw1a1aa"), Some("SW1A 1AA".into()));
+        assert_eq!(normalize("GB", "WC2R  0JR"), Some("WC2R 0JR".into()));
+        assert_eq!(normalize("GB", "London"), None);
+        assert_eq!(normalize("NL", "1234AB"), Some("1234 AB".into()));
+        assert_eq!(normalize("NL", "NL-1234 ab"), Some("1234 AB".into()));
+        assert_eq!(normalize("US", "02134 1234"), Some("02134-1234".into()));
+        assert_eq!(normalize("CA", "k1a0b1"), Some("K1A 0B1".into()));
+        assert_eq!(normalize("JP", "1000001"), Some("100-0001".into()));
+        assert_eq!(normalize("LV", "1050"), Some("LV-1050".into()));
+        assert_eq!(normalize("LV", "lv-1050"), Some("LV-1050".into()));
+        assert_eq!(normalize("BR", " 01310-100 "), Some("01310-100".into()));
+        assert_eq!(normalize("BR", " "), None);
+    }
+}
@@ -0,0 +1,282 @@
+//! Regions to restrict the output of the parse stage to,
+//! given as bounding box or as polygon in Osmosis `.poly` or GeoJSON format.
+use std::{fs, path::Path, str::FromStr};
+
+use serde_json::Value;
+
+/// Degrees, as (longitude, latitude).
+type Point = (f64, f64);
+type Ring = Vec<Point>;
+
+fn to_degrees(decimicro: i32) -> f64 {
+    decimicro as f64 / 10_000_000.
+}
+
+/// `min_lon,min_lat,max_lon,max_lat` in degrees, like the `bbox` of GeoJSON.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    min_lon: f64,
+    min_lat: f64,
+    max_lon: f64,
+    max_lat: f64,
+}
+
+impl FromStr for BoundingBox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid bounding box \"{}\"", s))?;
+        match values[..] {
+            [min_lon, min_lat, max_lon, max_lat] if min_lon <= max_lon && min_lat <= max_lat => {
+                Ok(Self {
+                    min_lon,
+                    min_lat,
+                    max_lon,
+                    max_lat,
+                })
+            }
+            _ => Err(format!(
+                "Expected bounding box as min_lon,min_lat,max_lon,max_lat, got \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+impl BoundingBox {
+    /// Coordinates in decimicro degrees, as computed by the parse stage.
+    pub fn contains(&self, long: i32, lat: i32) -> bool {
+        let (long, lat) = (to_degrees(long), to_degrees(lat));
+        self.min_lon <= long && long <= self.max_lon && self.min_lat <= lat && lat <= self.max_lat
+    }
+}
+
+/// An outer ring with the holes cut into it.
+#[derive(Debug, Clone, PartialEq)]
+struct Area {
+    outer: Ring,
+    holes: Vec<Ring>,
+}
+
+/// Area covered by any outer ring, but none of its holes.
+/// Another outer ring may lie within a hole, like an island in a lake.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Polygon {
+    areas: Vec<Area>,
+}
+
+/// Even-odd rule, points on the border are not necessarily contained.
+fn ring_contains(ring: &Ring, (x, y): Point) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let ((xi, yi), (xj, yj)) = (ring[i], ring[j]);
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+fn parse_point(value: &Value) -> Result<Point, String> {
+    match value.as_array().map(|a| &a[..]) {
+        Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+            (Some(lon), Some(lat)) => Ok((lon, lat)),
+            _ => Err(format!("Invalid position {}", value)),
+        },
+        _ => Err(format!("Invalid position {}", value)),
+    }
+}
+
+fn parse_ring(value: &Value) -> Result<Ring, String> {
+    value
+        .as_array()
+        .ok_or(format!("Invalid linear ring {}", value))?
+        .iter()
+        .map(parse_point)
+        .collect()
+}
+
+impl Polygon {
+    /// Read a `.poly` or GeoJSON file, depending on the file extension.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read polygon {:?}: {}", path, e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("poly") => Self::from_poly(&text),
+            Some("geojson") | Some("json") => Self::from_geojson(&text),
+            _ => Err(format!(
+                "Unknown polygon format {:?}, expected .poly or .geojson",
+                path
+            )),
+        }
+    }
+
+    /// Osmosis polygon filter file format, as used for the Geofabrik extracts.
+    /// Sections starting with `!` are holes.
+    /// Holes belong to the outer rings containing them.
+    pub fn from_poly(text: &str) -> Result<Self, String> {
+        let (mut outer, mut holes) = (Vec::new(), Vec::new());
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty()).skip(1);
+        while let Some(header) = lines.next() {
+            if header == "END" {
+                let areas = outer
+                    .into_iter()
+                    .map(|outer| Area {
+                        holes: holes
+                            .iter()
+                            .filter(|hole: &&Ring| {
+                                hole.first()
+                                    .is_some_and(|point| ring_contains(&outer, *point))
+                            })
+                            .cloned()
+                            .collect(),
+                        outer,
+                    })
+                    .collect();
+                return Ok(Self { areas });
+            }
+            let mut ring = Vec::new();
+            loop {
+                let line = lines
+                    .next()
+                    .ok_or(format!("Section \"{}\" is missing its END", header))?;
+                if line == "END" {
+                    break;
+                }
+                let point: Vec<f64> = line
+                    .split_whitespace()
+                    .map(|v| v.parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("Invalid coordinates \"{}\"", line))?;
+                match point[..] {
+                    [lon, lat] => ring.push((lon, lat)),
+                    _ => return Err(format!("Invalid coordinates \"{}\"", line)),
+                }
+            }
+            match header.starts_with('!') {
+                true => holes.push(ring),
+                false => outer.push(ring),
+            }
+        }
+        Err("Polygon is missing its final END".into())
+    }
+
+    /// A `Polygon` or `MultiPolygon` geometry, or a feature or feature collection of those.
+    pub fn from_geojson(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let mut polygon = Self::default();
+        polygon.add_geojson(&value)?;
+        Ok(polygon)
+    }
+
+    fn add_rings(&mut self, rings: &Value) -> Result<(), String> {
+        let rings = rings
+            .as_array()
+            .ok_or(format!("Invalid polygon coordinates {}", rings))?;
+        // The first ring is the exterior, all others are holes.
+        let mut rings = rings.iter().map(parse_ring);
+        let outer = match rings.next() {
+            Some(outer) => outer?,
+            None => return Ok(()),
+        };
+        let holes = rings.collect::<Result<_, _>>()?;
+        self.areas.push(Area { outer, holes });
+        Ok(())
+    }
+
+    fn add_geojson(&mut self, value: &Value) -> Result<(), String> {
+        let empty = Vec::new();
+        match value["type"].as_str() {
+            Some("FeatureCollection") => {
+                for feature in value["features"].as_array().unwrap_or(&empty) {
+                    self.add_geojson(feature)?;
+                }
+            }
+            Some("Feature") => self.add_geojson(&value["geometry"])?,
+            Some("Polygon") => self.add_rings(&value["coordinates"])?,
+            Some("MultiPolygon") => {
+                for rings in value["coordinates"].as_array().unwrap_or(&empty) {
+                    self.add_rings(rings)?;
+                }
+            }
+            other => {
+                return Err(format!(
+                    "Unsupported GeoJSON type {:?}, expected (Multi)Polygon",
+                    other
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Coordinates in decimicro degrees, as computed by the parse stage.
+    pub fn contains(&self, long: i32, lat: i32) -> bool {
+        let point = (to_degrees(long), to_degrees(lat));
+        self.areas.iter().any(|area| {
+            ring_contains(&area.outer, point)
+                && !area.holes.iter().any(|hole| ring_contains(hole, point))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Square around central Berlin with a hole around Alexanderplatz, and an island in the hole.
+    const POLY: &str = "berlin
+1
+    13.30 52.48
+    13.50 52.48
+    13.50 52.56
+    13.30 52.56
+END
+!2
+    13.40 52.52
+    13.42 52.52
+    13.42 52.53
+    13.40 52.53
+END
+3
+    13.405 52.522
+    13.415 52.522
+    13.415 52.528
+    13.405 52.528
+END
+END
+";
+
+    #[test]
+    fn poly_and_geojson() {
+        let geojson = r#"{"type": "Feature", "properties": {}, "geometry": {"type": "MultiPolygon", "coordinates": [[
+            [[13.30, 52.48], [13.50, 52.48], [13.50, 52.56], [13.30, 52.56], [13.30, 52.48]],
+            [[13.40, 52.52], [13.42, 52.52], [13.42, 52.53], [13.40, 52.53], [13.40, 52.52]]
+        ], [
+            [[13.405, 52.522], [13.415, 52.522], [13.415, 52.528], [13.405, 52.528], [13.405, 52.522]]
+        ]]}}"#;
+        for polygon in [
+            Polygon::from_poly(POLY).unwrap(),
+            Polygon::from_geojson(geojson).unwrap(),
+        ] {
+            assert!(polygon.contains(133_800_000, 525_300_000));
+            assert!(!polygon.contains(134_020_000, 525_210_000));
+            assert!(polygon.contains(134_100_000, 525_250_000));
+            assert!(!polygon.contains(116_000_000, 482_000_000));
+        }
+    }
+
+    #[test]
+    fn bounding_box() {
+        let bbox: BoundingBox = "13.3,52.48,13.5,52.56".parse().unwrap();
+        assert!(bbox.contains(133_800_000, 525_300_000));
+        assert!(!bbox.contains(116_000_000, 482_000_000));
+        assert!("13.5,52.48,13.3,52.56".parse::<BoundingBox>().is_err());
+        assert!("13.3,52.48".parse::<BoundingBox>().is_err());
+    }
+}
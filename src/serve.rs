@@ -10,7 +10,7 @@ use tokio::runtime::Builder;
 
 use crate::{api::get_app, compress::World};
 
-fn parse_into_world(f: PathBuf) -> Result<World, String> {
+pub fn parse_into_world(f: PathBuf) -> Result<World, String> {
     info!("Loading from world file {:?}...", f);
     let reader = OpenOptions::new()
         .read(true)
@@ -18,7 +18,7 @@ fn parse_into_world(f: PathBuf) -> Result<World, String> {
         .open(f)
         .map_err(|e| e.to_string())?;
     let buf_reader = BufReader::new(reader);
-    bincode::deserialize_from(buf_reader).map_err(|e| e.to_string())
+    World::read(buf_reader)
 }
 
 async fn start_server(w: World, ip: IpAddr, port: u16) -> ! {